@@ -0,0 +1,186 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use textocx::transpile;
+
+const USAGE: &str = "\
+Usage: textocx [OPTIONS] [INPUT]
+
+Convert a TeX snippet to MSOffice pastable format.
+
+Arguments:
+  [INPUT]                  TeX file to convert, `-` for stdin [default: -]
+
+Options:
+  -o, --output <FILE>      Write the result to FILE instead of stdout
+  -f, --format <FORMAT>    Output flavour: cf-html, html or omml [default: cf-html]
+      --italic-math        Wrap formulas in <i>
+      --preserve-spaces    Keep the spaces and line breaks of text
+      --latex-layout       Lay out paragraphs and line breaks as LaTeX does
+      --number-sections    Number the headings of sections
+      --equation-offset <N>
+                           Number equations from N+1, to continue a previous snippet [default: 0]
+      --equation-fields    Number equations by Word fields, which Word keeps up to date
+      --keep-comments      Keep `%` comments as hidden HTML comments
+      --preamble <FILE>    Load the macro definitions in FILE for every formula
+      --bibliography <FILE>
+                           Resolve citations with the BibTeX or CSL-JSON entries in FILE
+      --author-year        Label citations by authors and years instead of numbers
+      --base-dir <DIR>     Find included images in DIR [default: the directory of INPUT]
+      --tolerant           Render failed formulas as placeholders and report them as warnings
+  -j, --jobs <N>           Number of worker threads [default: 2]
+  -h, --help               Print this help
+";
+
+#[derive(Debug)]
+struct Args {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    nworkers: usize,
+    preamble: Option<PathBuf>,
+    bibliography: Option<PathBuf>,
+    base_dir: Option<PathBuf>,
+    job: transpile::Job,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut res = Args {
+        input: None,
+        output: None,
+        nworkers: 2,
+        preamble: None,
+        bibliography: None,
+        base_dir: None,
+        job: Default::default(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => res.output = Some(value(&arg)?.into()),
+            "-f" | "--format" => {
+                res.job.format = match value(&arg)?.as_str() {
+                    "cf-html" => transpile::Format::CfHtml,
+                    "html" => transpile::Format::Fragment,
+                    "omml" => transpile::Format::Omml,
+                    x => return Err(format!("unknown format: {}", x)),
+                }
+            }
+            "-j" | "--jobs" => {
+                res.nworkers = value(&arg)?
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or("--jobs expects a positive integer")?
+            }
+            "--equation-offset" => {
+                res.job.equation_offset = value(&arg)?
+                    .parse()
+                    .map_err(|_| "--equation-offset expects a non-negative integer")?
+            }
+            "--preamble" => res.preamble = Some(value(&arg)?.into()),
+            "--bibliography" => res.bibliography = Some(value(&arg)?.into()),
+            "--base-dir" => res.base_dir = Some(value(&arg)?.into()),
+            "--author-year" => res.job.citation_style = transpile::CitationStyle::AuthorYear,
+            "--italic-math" => res.job.italic_math = true,
+            "--preserve-spaces" => res.job.preserve_spaces = true,
+            "--latex-layout" => res.job.latex_layout = true,
+            "--number-sections" => res.job.number_sections = true,
+            "--equation-fields" => res.job.equation_fields = true,
+            "--tolerant" => res.job.tolerant = true,
+            "--keep-comments" => res.job.keep_comments = true,
+            "-" => res.input = None,
+            x if x.starts_with('-') => return Err(format!("unknown option: {}", x)),
+            x => res.input = Some(x.into()),
+        }
+    }
+    Ok(Some(res))
+}
+
+pub fn run<I: Iterator<Item = String>>(args: I) -> ExitCode {
+    let mut args = match parse_args(args) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let res = match &args.input {
+        Some(path) => fs::read_to_string(path),
+        None => {
+            let mut buf = String::new();
+            io::stdin()
+                .read_to_string(&mut buf)
+                .map(|_| buf)
+        }
+    };
+    args.job.tex_code = match res {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: fail to read input: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    args.job.base_dir = match (args.base_dir, &args.input) {
+        (Some(dir), _) => dir,
+        (None, Some(path)) => path
+            .parent()
+            .map(Into::into)
+            .unwrap_or_default(),
+        (None, None) => PathBuf::new(),
+    };
+
+    let mut solver = transpile::Solver::new(args.nworkers);
+    if let Some(path) = &args.preamble {
+        if let Err(e) = solver.load_preamble(path) {
+            report("error", &e);
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(path) = &args.bibliography {
+        if let Err(e) = solver.load_bibliography(path) {
+            report("error", &e);
+            return ExitCode::FAILURE;
+        }
+    }
+    let output = match solver.solve(args.job) {
+        Ok(output) => output,
+        Err(e) => {
+            report("error", &e);
+            return ExitCode::FAILURE;
+        }
+    };
+    for e in &output.errors {
+        report("warning", e);
+    }
+
+    let res = match &args.output {
+        Some(path) => fs::write(path, output.content),
+        None => io::stdout().write_all(output.content.as_bytes()),
+    };
+    if let Err(e) = res {
+        eprintln!("error: fail to write output: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn report(level: &str, e: &transpile::Error) {
+    eprintln!("{}: {}", level, e);
+    if let Some(loc) = e.location() {
+        eprintln!("{}", loc.snippet);
+    }
+}
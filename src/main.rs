@@ -1,7 +1,25 @@
-mod transpile;
+mod cli;
+#[cfg(all(windows, feature = "gui"))]
 mod ui;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let _ui = ui::App::build_and_run()?;
-    Ok(())
+use std::process::ExitCode;
+
+#[cfg(all(windows, feature = "gui"))]
+fn main() -> ExitCode {
+    // Without arguments, launch the GUI. Use `textocx -` to read from stdin.
+    if std::env::args().len() > 1 {
+        return cli::run(std::env::args().skip(1));
+    }
+    match ui::App::build_and_run() {
+        Ok(_ui) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(not(all(windows, feature = "gui")))]
+fn main() -> ExitCode {
+    cli::run(std::env::args().skip(1))
 }
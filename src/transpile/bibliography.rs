@@ -0,0 +1,456 @@
+use nom::branch::alt;
+use nom::bytes::complete::{self as bc};
+use nom::character::complete::{self as cc};
+use nom::combinator::{all_consuming, opt};
+use nom::multi::{separated_list0, separated_list1};
+use nom::sequence::{delimited, preceded, separated_pair, terminated};
+use nom::{IResult, Offset, Parser};
+use std::collections::HashMap;
+
+use super::{error::Error, macros, symbols};
+
+/// A name in the list of authors.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(super) struct Name {
+    pub family: String,
+    pub given: String,
+}
+
+/// An entry of the bibliography, with its fields in plain text.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(super) struct Entry {
+    pub key: String,
+    pub authors: Vec<Name>,
+    pub title: String,
+    /// The journal, or the book of a chapter or a paper.
+    pub container: String,
+    pub volume: String,
+    pub issue: String,
+    pub pages: String,
+    pub publisher: String,
+    pub year: String,
+}
+
+impl Entry {
+    /// The authors as cited, like `Knuth`, `Knuth and Plass` or
+    /// `Knuth et al.`
+    pub fn short_authors(&self) -> String {
+        match self.authors.as_slice() {
+            [] => self.title.clone(),
+            [a] => a.family.clone(),
+            [a, b] => format!("{} and {}", a.family, b.family),
+            [a, ..] => format!("{} et al.", a.family),
+        }
+    }
+    /// The authors as listed in the references, like `D. E. Knuth and
+    /// M. F. Plass`.
+    pub fn full_authors(&self) -> String {
+        let names = self
+            .authors
+            .iter()
+            .map(|name| {
+                let initials = name
+                    .given
+                    .split([' ', '~', '\u{a0}'])
+                    .filter_map(|part| part.chars().next())
+                    .map(|c| format!("{}. ", c))
+                    .collect::<String>();
+                initials + &name.family
+            })
+            .collect::<Vec<_>>();
+        match names.as_slice() {
+            [] => String::new(),
+            [a] => a.clone(),
+            [a, b] => format!("{} and {}", a, b),
+            [init @ .., last] => format!("{}, and {}", init.join(", "), last),
+        }
+    }
+}
+
+/// The entries loaded from a BibTeX or CSL-JSON file.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Bibliography {
+    entries: Vec<Entry>,
+    /// The indices of the entries, by their keys.
+    keys: HashMap<String, usize>,
+}
+
+impl Bibliography {
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.keys
+            .get(key)
+            .map(|&i| &self.entries[i])
+    }
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+/// Parses a bibliography, which is CSL-JSON if it is an array, or BibTeX
+/// otherwise.
+pub(super) fn parse(source: &str) -> Result<Bibliography, Error> {
+    let entries = if source.trim_start().starts_with('[') {
+        csl_json(source)?
+    } else {
+        bibtex(source)?
+    };
+    let keys = entries
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (e.key.clone(), i))
+        .collect();
+    Ok(Bibliography { entries, keys })
+}
+
+fn csl_json(source: &str) -> Result<Vec<Entry>, Error> {
+    use serde_json::Value;
+
+    let items: Vec<Value> = serde_json::from_str(source)
+        .map_err(|e| Error::BadInput(format!("malformed CSL-JSON: {}", e).into(), None))?;
+    let text = |item: &Value, name: &str| match &item[name] {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        _ => String::new(),
+    };
+    let entries = items
+        .iter()
+        .map(|item| Entry {
+            key: text(item, "id"),
+            authors: item["author"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|name| match name["literal"].as_str() {
+                    Some(literal) => Name {
+                        family: literal.into(),
+                        given: String::new(),
+                    },
+                    None => Name {
+                        family: text(name, "family"),
+                        given: text(name, "given"),
+                    },
+                })
+                .collect(),
+            title: text(item, "title"),
+            container: text(item, "container-title"),
+            volume: text(item, "volume"),
+            issue: text(item, "issue"),
+            pages: text(item, "page").replace('-', "–"),
+            publisher: text(item, "publisher"),
+            year: match &item["issued"]["date-parts"][0][0] {
+                Value::String(year) => year.clone(),
+                Value::Number(year) => year.to_string(),
+                _ => text(&item["issued"], "literal"),
+            },
+        })
+        .collect();
+    Ok(entries)
+}
+
+#[test]
+fn test_csl_json() {
+    let entries = csl_json(
+        r#"[{"id": "k", "type": "book", "title": "The TeXbook",
+            "author": [{"family": "Knuth", "given": "Donald E."}, {"literal": "ACM"}],
+            "page": "1-2", "issued": {"date-parts": [[1984, 1]]}}]"#,
+    )
+    .unwrap();
+    assert_eq!(entries[0].key, "k");
+    assert_eq!(entries[0].full_authors(), "D. E. Knuth and ACM");
+    assert_eq!(entries[0].pages, "1–2");
+    assert_eq!(entries[0].year, "1984");
+    assert!(csl_json("[{]").is_err());
+}
+
+/// A piece of a BibTeX value.
+enum Value<'a> {
+    Literal(&'a str),
+    /// A string defined by `@string`, or a month like `jan`.
+    Variable(&'a str),
+}
+
+fn bibtex(source: &str) -> Result<Vec<Entry>, Error> {
+    let mut strings: HashMap<String, String> = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ]
+    .into_iter()
+    .map(|month| (month[..3].to_lowercase(), month.into()))
+    .collect();
+    let mut entries = vec![];
+    let mut input = source;
+    // text outside of entries is ignored, as BibTeX does
+    while let Some(start) = input.find('@') {
+        input = &input[start..];
+        let malformed = |at: &str| {
+            Error::bad_input(
+                source,
+                source.offset(at),
+                "malformed entry in bibliography".into(),
+            )
+        };
+        let (rest, (typ, body)) = entry(input).map_err(|_| malformed(input))?;
+        input = rest;
+        let typ = typ.to_lowercase();
+        if typ == "comment" || typ == "preamble" {
+            continue;
+        }
+        let (key, body) = match typ.as_str() {
+            "string" => ("", body),
+            _ => body
+                .split_once(',')
+                .unwrap_or((body, "")),
+        };
+        let (_, fields) = fields(body).map_err(|_| malformed(body))?;
+        let mut fields = fields
+            .into_iter()
+            .map(|(name, values)| {
+                let value = values
+                    .iter()
+                    .map(|v| match v {
+                        Value::Literal(s) => s,
+                        Value::Variable(name) => strings
+                            .get(&name.to_lowercase())
+                            .map_or("", String::as_str),
+                    })
+                    .collect::<String>();
+                (name.to_lowercase(), value)
+            })
+            .collect::<HashMap<_, _>>();
+        if typ == "string" {
+            strings.extend(fields);
+            continue;
+        }
+        let authors = names(
+            &fields
+                .remove("author")
+                .unwrap_or_default(),
+        );
+        let mut take = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| fields.remove(*name))
+                .map_or(String::new(), |value| plain(&value))
+        };
+        entries.push(Entry {
+            key: key.trim().into(),
+            authors,
+            title: take(&["title"]),
+            container: take(&["journal", "journaltitle", "booktitle"]),
+            volume: take(&["volume"]),
+            issue: take(&["number", "issue"]),
+            pages: take(&["pages"]),
+            publisher: take(&["publisher", "school", "institution", "organization"]),
+            year: match take(&["year"]) {
+                // the date of biblatex, like `1984-01`
+                year if year.is_empty() => take(&["date"])
+                    .chars()
+                    .take(4)
+                    .collect(),
+                year => year,
+            },
+        });
+    }
+    Ok(entries)
+}
+
+/// Parses `@type{...}` or `@type(...)`, and returns the type and the body.
+fn entry(input: &str) -> IResult<&str, (&str, &str)> {
+    let typ = preceded(bc::tag("@").and(cc::multispace0), cc::alpha1);
+    let body = alt((
+        delimited(bc::tag("{"), balanced('}'), bc::tag("}")),
+        delimited(bc::tag("("), balanced(')'), bc::tag(")")),
+    ));
+    separated_pair(typ, cc::multispace0, body)(input)
+}
+
+/// Parses the fields like `title = {The {\TeX}book}, year = 1984`.
+fn fields(input: &str) -> IResult<&str, Vec<(&str, Vec<Value<'_>>)>> {
+    let name = delimited(
+        cc::multispace0,
+        bc::take_while1(|c: char| c.is_alphanumeric() || "_-:.".contains(c)),
+        cc::multispace0,
+    );
+    let value = alt((
+        delimited(bc::tag("{"), balanced('}'), bc::tag("}")).map(Value::Literal),
+        delimited(bc::tag("\""), balanced('"'), bc::tag("\"")).map(Value::Literal),
+        cc::digit1.map(Value::Literal),
+        bc::take_while1(|c: char| c.is_alphanumeric() || "_-:.".contains(c)).map(Value::Variable),
+    ));
+    let values = separated_list1(
+        delimited(cc::multispace0, bc::tag("#"), cc::multispace0),
+        value,
+    );
+    let field = separated_pair(
+        name,
+        bc::tag("=").and(cc::multispace0),
+        terminated(values, cc::multispace0),
+    );
+    all_consuming(terminated(
+        separated_list0(bc::tag(","), field),
+        opt(bc::tag(",")).and(cc::multispace0),
+    ))(input)
+}
+
+/// Takes the text up to `close` out of braces, like the `}` closing the
+/// current group.
+fn balanced(close: char) -> impl Fn(&str) -> IResult<&str, &str> {
+    move |input| {
+        let mut depth = 0;
+        for (i, c) in input.char_indices() {
+            match c {
+                c if c == close && depth == 0 => return Ok((&input[i..], &input[..i])),
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => (),
+            }
+        }
+        Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Eof,
+        )))
+    }
+}
+
+#[test]
+fn test_bibtex() {
+    let entries = bibtex(
+        r#"% comment
+@String{tub = "TUGboat"}
+@article{knuth81,
+  author = {Knuth, Donald E. and Michael F. Plass},
+  title = {Breaking Paragraphs into {L}ines},
+  journal = tub # { Special},
+  year = 1981, month = nov,
+  pages = "1119--1184",
+}
+@book(lamport, author = {Leslie Lamport}, title = "{\LaTeX}: A Document Preparation System", date = {1994-01})"#,
+    )
+    .unwrap();
+    assert_eq!(
+        entries[0],
+        Entry {
+            key: "knuth81".into(),
+            authors: vec![
+                Name {
+                    family: "Knuth".into(),
+                    given: "Donald E.".into()
+                },
+                Name {
+                    family: "Plass".into(),
+                    given: "Michael F.".into()
+                },
+            ],
+            title: "Breaking Paragraphs into Lines".into(),
+            container: "TUGboat Special".into(),
+            pages: "1119–1184".into(),
+            year: "1981".into(),
+            ..Default::default()
+        }
+    );
+    assert_eq!(entries[1].short_authors(), "Lamport");
+    assert_eq!(entries[1].title, "LaTeX: A Document Preparation System");
+    assert_eq!(entries[1].year, "1994");
+    let e = bibtex("@article{a,\n  title = {x}\n  year = 1}").unwrap_err();
+    assert_eq!(e.location().map(|l| l.line), Some(1));
+}
+
+/// Splits a list of names like `Knuth, Donald and Leslie Lamport`.
+fn names(value: &str) -> Vec<Name> {
+    split_top_level(value, " and ")
+        .into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && *name != "others")
+        .map(|name| match split_top_level(name, ",").as_slice() {
+            [family, given, ..] => Name {
+                family: plain(family),
+                given: plain(given),
+            },
+            _ => {
+                let words = split_top_level(name, " ");
+                let (family, given) = words.split_last().unwrap();
+                Name {
+                    family: plain(family),
+                    given: plain(&given.join(" ")),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Splits `value` at `sep` out of braces.
+fn split_top_level<'v>(value: &'v str, sep: &str) -> Vec<&'v str> {
+    let mut res = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in value.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 && i >= start && value[i..].starts_with(sep) => {
+                res.push(&value[start..i]);
+                start = i + sep.len();
+            }
+            _ => (),
+        }
+    }
+    res.push(&value[start..]);
+    res.retain(|s| !s.trim().is_empty());
+    res
+}
+
+/// Converts a BibTeX value to plain text, where the braces are dropped and
+/// the symbols and accents are translated.
+fn plain(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    let mut rest = value;
+    while !rest.is_empty() {
+        let end = rest
+            .find(['\\', '{', '}', '$'])
+            .unwrap_or(rest.len());
+        res.push_str(&symbols::ligatures(&rest[..end]));
+        rest = &rest[end..];
+        let Ok((after, name)) = macros::control_sequence(rest) else {
+            // a brace, a `$` or a backslash at the end
+            rest = rest.get(1..).unwrap_or_default();
+            continue;
+        };
+        rest = after;
+        if let Some(s) = symbols::symbol(name) {
+            res.push_str(s);
+        } else if let Some(accent) = symbols::accent(name) {
+            // the accented letter, which may be in braces
+            let arg = rest.trim_start_matches([' ', '{']);
+            let len = arg
+                .chars()
+                .next()
+                .map_or(0, char::len_utf8);
+            res.push_str(&symbols::put_accent(&arg[..len], accent));
+            rest = &arg[len..];
+        }
+        // other commands like `\emph` are dropped, with their arguments kept
+        if name[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            rest = rest.trim_start();
+        }
+    }
+    res.split_ascii_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn test_plain() {
+    assert_eq!(plain(r#"G{\"o}del's \emph{Proof}"#), "Gödel’s Proof");
+    assert_eq!(plain(r"Erd\H os and {\TeX} in $x$"), "Erdős and TeX in x");
+    assert_eq!(plain("pp.~1--2\n  end"), "pp.\u{a0}1–2 end");
+    assert_eq!(plain(r"\c{c}a\,b\"), "ça\u{2009}b");
+}
@@ -1,8 +1,14 @@
+use std::sync::Arc;
+
+use super::macros::Macro;
+
 #[derive(Debug)]
 pub(super) struct Request {
+    pub job: u64,
     pub loc: usize,
     pub tex: String,
     pub display_mode: bool,
+    pub macros: Arc<Vec<Macro>>,
 }
 
 impl<'js> rq::IntoJs<'js> for Request {
@@ -11,6 +17,13 @@ impl<'js> rq::IntoJs<'js> for Request {
             let obj = rq::Object::new(ctx.clone())?;
             obj.set("input", self.tex.into_js(ctx)?)?;
             obj.set("displayMode", self.display_mode.into_js(ctx)?)?;
+            obj.set("macros", {
+                let macros = rq::Object::new(ctx.clone())?;
+                for m in self.macros.iter() {
+                    macros.set(m.name.as_str(), m.body.as_str())?;
+                }
+                macros
+            })?;
             obj.into()
         })
     }
@@ -18,6 +31,7 @@ impl<'js> rq::IntoJs<'js> for Request {
 
 #[derive(Debug)]
 pub(super) struct Response {
+    pub job: u64,
     pub loc: usize,
     pub omml: Result<String, String>,
 }
@@ -0,0 +1,122 @@
+use std::{borrow::Cow, ops::Range};
+
+/// The replacements made to a formula before it is sent to Temml, by which a
+/// position in the result is traced back to the original.
+///
+/// Positions are counted in UTF-16 units, as in Temml error messages.
+#[derive(Debug, Default)]
+pub(super) struct Edits {
+    /// The replaced ranges and the lengths of their replacements, in order,
+    /// for each round of editing.
+    rounds: Vec<Vec<(Range<usize>, usize)>>,
+}
+
+impl Edits {
+    /// Appends the rounds of `edits`, which are made to the result of these.
+    pub fn append(&mut self, mut edits: Edits) {
+        self.rounds.append(&mut edits.rounds);
+    }
+
+    /// Traces `pos` in the result back to the original, where a position
+    /// within a replacement goes to the start of what it replaces.
+    pub fn origin(&self, mut pos: usize) -> usize {
+        for round in self.rounds.iter().rev() {
+            // from the original to the result, before `pos`
+            let mut shift = 0;
+            for (range, len) in round {
+                let start = range.start as isize + shift;
+                if (pos as isize) < start {
+                    break;
+                }
+                if (pos as isize) < start + *len as isize {
+                    shift = pos as isize - range.start as isize;
+                    break;
+                }
+                shift += *len as isize - range.len() as isize;
+            }
+            pos = (pos as isize - shift) as usize;
+        }
+        pos
+    }
+}
+
+/// Builds a string from `tex` with some ranges replaced, and keeps the edits.
+pub(super) struct Editor<'t> {
+    tex: &'t str,
+    res: String,
+    /// The end of the last replaced range, in bytes and in UTF-16 units.
+    last: usize,
+    last_units: usize,
+    round: Vec<(Range<usize>, usize)>,
+}
+
+impl<'t> Editor<'t> {
+    pub fn new(tex: &'t str) -> Self {
+        Editor {
+            tex,
+            res: String::new(),
+            last: 0,
+            last_units: 0,
+            round: vec![],
+        }
+    }
+
+    /// Replaces a range of bytes, which comes after those replaced before.
+    pub fn replace(&mut self, range: Range<usize>, with: &str) {
+        let start = self.last_units + units(&self.tex[self.last..range.start]);
+        let end = start + units(&self.tex[range.clone()]);
+        self.res
+            .push_str(&self.tex[self.last..range.start]);
+        self.res.push_str(with);
+        self.round
+            .push((start..end, units(with)));
+        self.last = range.end;
+        self.last_units = end;
+    }
+
+    /// Returns the result, which is borrowed if nothing is replaced.
+    pub fn finish(mut self) -> (Cow<'t, str>, Edits) {
+        if self.round.is_empty() {
+            return (self.tex.into(), Edits::default());
+        }
+        self.res
+            .push_str(&self.tex[self.last..]);
+        let edits = Edits {
+            rounds: vec![self.round],
+        };
+        (self.res.into(), edits)
+    }
+}
+
+/// The length of `s` in UTF-16 units.
+pub(super) fn units(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+/// Converts a position in UTF-16 units to bytes.
+pub(super) fn byte_offset(s: &str, pos: usize) -> usize {
+    let mut units = 0;
+    s.char_indices()
+        .find(|(_, c)| {
+            units += c.len_utf16();
+            units > pos
+        })
+        .map_or(s.len(), |(i, _)| i)
+}
+
+#[test]
+fn test_edits() {
+    let mut editor = Editor::new("a𝔸bcd");
+    editor.replace(1..5, "xyz");
+    editor.replace(6..7, "");
+    let (res, mut edits) = editor.finish();
+    assert_eq!(res, "axyzbd");
+    let mut editor = Editor::new(&res);
+    editor.replace(0..0, "__");
+    let (res, more) = editor.finish();
+    assert_eq!(res, "__axyzbd");
+    edits.append(more);
+    // `a`, the replacement of `𝔸`, `b` and `d`
+    assert_eq!([2, 4, 6, 7].map(|pos| edits.origin(pos)), [0, 1, 3, 5]);
+    assert!(matches!(Editor::new("a").finish(), (Cow::Borrowed("a"), _)));
+}
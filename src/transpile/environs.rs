@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EnvironKind {
+    /// The whole environment, including `\begin` and `\end`, is sent to Temml.
+    Math,
+    /// The environment is part of the text, and may contain math.
+    Text,
+}
+
+/// The registry of environments known to the parser.
+///
+/// Environments not in the registry are treated as [`EnvironKind::Text`].
+#[derive(Debug, Clone)]
+pub struct Environs(HashMap<String, EnvironKind>);
+
+// https://temml.org/docs/en/supported#environments
+const MATH_ENVIRONS: &str = "
+    equation equation* align align* alignat alignat* aligned alignedat
+    gather gather* gathered multline multline* split CD
+    array darray subarray cases dcases rcases drcases
+    matrix matrix* smallmatrix pmatrix pmatrix* bmatrix bmatrix*
+    Bmatrix Bmatrix* vmatrix vmatrix* Vmatrix Vmatrix*";
+
+const TEXT_ENVIRONS: &str = "
+    itemize enumerate description center flushleft flushright
+    quote quotation verse abstract";
+
+impl Default for Environs {
+    fn default() -> Self {
+        let mut envs = Environs(HashMap::new());
+        for (names, kind) in [
+            (MATH_ENVIRONS, EnvironKind::Math),
+            (TEXT_ENVIRONS, EnvironKind::Text),
+        ] {
+            for name in names.split_whitespace() {
+                envs.insert(name, kind);
+            }
+        }
+        envs
+    }
+}
+
+impl Environs {
+    /// Registers `name`, overriding its previous kind if any.
+    pub fn insert<S: Into<String>>(&mut self, name: S, kind: EnvironKind) {
+        self.0.insert(name.into(), kind);
+    }
+    pub fn remove(&mut self, name: &str) {
+        self.0.remove(name);
+    }
+    pub fn kind(&self, name: &str) -> EnvironKind {
+        self.0
+            .get(name)
+            .copied()
+            .unwrap_or(EnvironKind::Text)
+    }
+    pub fn is_math(&self, name: &str) -> bool {
+        self.kind(name) == EnvironKind::Math
+    }
+}
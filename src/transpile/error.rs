@@ -1,30 +1,144 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, ops::Range};
+
+use super::edits::{byte_offset, Edits};
 
 #[derive(Debug)]
 pub enum Error {
-    BadInput(Cow<'static, str>),
-    JS(String),
+    BadInput(Cow<'static, str>, Option<Location>),
+    JS(String, Option<Location>),
+    Cancelled,
+}
+
+/// A position in [`Job::tex_code`](super::Job::tex_code), or in the preamble.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    /// Byte offset into the source.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters.
+    pub column: usize,
+    /// The offending source line, followed by a line with a caret under the column.
+    pub snippet: String,
+}
+
+impl Location {
+    pub(super) fn new(source: &str, offset: usize) -> Location {
+        let offset = offset.min(source.len());
+        let line_start = source[..offset]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i);
+        let head = &source[line_start..offset];
+        // keep tabs so that the caret lines up
+        let pad = head
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        Location {
+            offset,
+            line: source[..line_start]
+                .matches('\n')
+                .count()
+                + 1,
+            column: head.chars().count() + 1,
+            snippet: format!(
+                "{}\n{}^",
+                source[line_start..line_end].trim_end_matches('\r'),
+                pad
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_location() {
+    let loc = Location::new("ab\n你好 $x$\n", 11);
+    assert_eq!((loc.line, loc.column), (2, 5));
+    assert_eq!(loc.snippet, "你好 $x$\n    ^");
 }
 
 impl Error {
-    pub(super) fn bad_input<S: std::error::Error>(msg: S) -> Error {
-        Error::BadInput(msg.to_string().into())
+    pub(super) fn bad_input(source: &str, offset: usize, msg: String) -> Error {
+        Error::BadInput(msg.into(), Some(Location::new(source, offset)))
+    }
+
+    /// Builds an error from the message of a formula that fails to render.
+    /// `span` is where the formula sits in `source`, and `edits` are those
+    /// made to it before it is sent to Temml.
+    pub(super) fn js(source: &str, span: Range<usize>, edits: &Edits, msg: String) -> Error {
+        // Temml counts in UTF-16 units, starting from 1
+        let loc = js_position(&msg)
+            .and_then(|pos| pos.checked_sub(1))
+            .map(|pos| byte_offset(&source[span.clone()], edits.origin(pos)))
+            .unwrap_or(0);
+        Error::JS(msg, Some(Location::new(source, span.start + loc)))
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::BadInput(_, loc) | Self::JS(_, loc) => loc.as_ref(),
+            Self::Cancelled => None,
+        }
     }
 }
 
+/// Extracts `N` from Temml messages like `... at position N: ...`.
+fn js_position(msg: &str) -> Option<usize> {
+    let (_, rest) = msg.rsplit_once(" at position ")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+#[test]
+fn test_js_error() {
+    let source = "foo $x^}$";
+    let e = Error::js(
+        source,
+        5..8,
+        &Edits::default(),
+        "ParseError: Temml parse error: Expected 'EOF', got '}' at position 3: x^}̲".into(),
+    );
+    assert_eq!(e.location().map(|l| l.offset), Some(7));
+    // as the environment is expanded before it is sent
+    let source = r"ab \begin{a}x\bad\end{a}";
+    let mut editor = super::edits::Editor::new(&source[3..]);
+    editor.replace(0..9, r"\begin{aligned}");
+    let (tex, edits) = editor.finish();
+    let e = Error::js(
+        source,
+        3..source.len(),
+        &edits,
+        format!(
+            "ParseError: Temml parse error: Undefined control sequence: \\bad at position {}: x\\bad",
+            tex.find(r"\bad").unwrap() + 1
+        ),
+    );
+    assert_eq!(e.location().map(|l| l.column), Some(14));
+}
+
 impl std::error::Error for Error {}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::BadInput(x) => {
+            Self::BadInput(x, _) => {
                 write!(f, "Bad Input: ")?;
-                f.write_str(x.as_ref())
+                f.write_str(x.as_ref())?
             }
-            Self::JS(x) => {
+            Self::JS(x, _) => {
                 write!(f, "JS Exception: ")?;
-                f.write_str(x)
+                f.write_str(x)?
             }
+            Self::Cancelled => write!(f, "Cancelled")?,
+        }
+        if let Some(loc) = self.location() {
+            write!(f, " (line {}, column {})", loc.line, loc.column)?;
         }
+        Ok(())
     }
 }
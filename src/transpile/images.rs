@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use base64::Engine;
+
+use super::{
+    error::Error,
+    parser::{Part, Type},
+};
+
+/// The width and height of the text, in pixels, as on a Letter page with the
+/// 1in margins of Word.
+const TEXT_WIDTH: f64 = 6.5 * 96.;
+const TEXT_HEIGHT: f64 = 9. * 96.;
+
+/// The extensions tried in turn for a path without one, as graphicx does.
+const EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif"];
+
+/// An image included by `\includegraphics`.
+#[derive(Debug)]
+pub(super) struct Image {
+    mime: &'static str,
+    data: Vec<u8>,
+    /// The width and height in pixels, as given in the header.
+    size: (u32, u32),
+}
+
+/// The images of a snippet, by their paths as written.
+pub(super) type Images = HashMap<String, Image>;
+
+impl Image {
+    /// Reads a PNG, JPEG or GIF image.
+    fn read(path: &Path) -> Result<Image, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        let (mime, size) = header(&data).ok_or("not a PNG, JPEG or GIF image")?;
+        Ok(Image { mime, data, size })
+    }
+
+    /// Returns the `<img>` element, sized by the options of `\includegraphics`
+    /// like `width=0.5\linewidth`.
+    pub fn tag(&self, options: &str) -> String {
+        let (w, h) = (self.size.0 as f64, self.size.1 as f64);
+        let (mut width, mut height, mut scale, mut keep_ratio) = (None, None, 1., false);
+        for option in options.split(',') {
+            let (key, value) = option
+                .split_once('=')
+                .unwrap_or((option, ""));
+            match key.trim() {
+                "width" => width = pixels(value),
+                "height" | "totalheight" => height = pixels(value),
+                "scale" => scale = value.trim().parse().unwrap_or(1.),
+                "keepaspectratio" => keep_ratio = value.trim() != "false",
+                _ => (),
+            }
+        }
+        let (width, height) = match (width, height) {
+            (Some(width), Some(height)) if keep_ratio => {
+                let ratio = (width / w).min(height / h);
+                (w * ratio, h * ratio)
+            }
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, h * width / w),
+            (None, Some(height)) => (w * height / h, height),
+            (None, None) => (w * scale, h * scale),
+        };
+        format!(
+            r#"<img width="{}" height="{}" src="data:{};base64,{}">"#,
+            width.round(),
+            height.round(),
+            self.mime,
+            base64::engine::general_purpose::STANDARD.encode(&self.data)
+        )
+    }
+}
+
+/// Reads the images included in the text parts of `source`, where the paths
+/// are relative to `base`, along with the errors of those failing to read.
+pub(super) fn load(source: &str, parts: &[Part], base: &Path) -> (Images, Vec<Error>) {
+    let mut images = Images::new();
+    let mut errors = vec![];
+    for part in parts
+        .iter()
+        .filter(|p| p.typ == Type::Text)
+    {
+        let text = part.as_str();
+        for (i, _) in text.match_indices("\\includegraphics") {
+            let Some(path) = path(&text[i + "\\includegraphics".len()..]) else {
+                continue;
+            };
+            if images.contains_key(path) {
+                continue;
+            }
+            match Image::read(&resolve(base, path)) {
+                Ok(image) => {
+                    images.insert(path.into(), image);
+                }
+                Err(e) => errors.push(Error::bad_input(
+                    source,
+                    part.span.start + i,
+                    format!("fail to read image {}: {}", path, e),
+                )),
+            }
+        }
+    }
+    (images, errors)
+}
+
+/// Returns the path after `\includegraphics`, skipping the star and options.
+fn path(tex: &str) -> Option<&str> {
+    let mut tex = tex
+        .strip_prefix('*')
+        .unwrap_or(tex)
+        .trim_start();
+    if let Some(rest) = tex.strip_prefix('[') {
+        tex = rest[rest.find(']')? + 1..].trim_start();
+    }
+    let rest = tex.strip_prefix('{')?;
+    Some(rest[..rest.find('}')?].trim())
+}
+
+/// Joins `path` to `base`, and finds the extension if it is omitted.
+fn resolve(base: &Path, path: &str) -> PathBuf {
+    let path = base.join(path);
+    if path.extension().is_some() || path.exists() {
+        return path;
+    }
+    EXTENSIONS
+        .iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|p| p.exists())
+        .unwrap_or(path)
+}
+
+/// Detects the type and size of an image from its header.
+fn header(data: &[u8]) -> Option<(&'static str, (u32, u32))> {
+    let be16 = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let be32 = |i: usize| Some(u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?));
+        return Some(("image/png", (be32(16)?, be32(20)?)));
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        let le16 = |i: usize| Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+        return Some(("image/gif", (le16(6)?, le16(8)?)));
+    }
+    if !data.starts_with(b"\xff\xd8") {
+        return None;
+    }
+    // the segments up to the start of frame
+    let mut i = 2;
+    loop {
+        while *data.get(i + 1)? == 0xff {
+            i += 1;
+        }
+        match data[i + 1] {
+            0xc0..=0xcf if !matches!(data[i + 1], 0xc4 | 0xc8 | 0xcc) => {
+                return Some(("image/jpeg", (be16(i + 7)?, be16(i + 5)?)));
+            }
+            0xda => return None,
+            _ => i += 2 + be16(i + 2)? as usize,
+        }
+    }
+}
+
+/// Converts a length in TeX to pixels, including fractions of the text width
+/// like `0.5\linewidth`.
+fn pixels(len: &str) -> Option<f64> {
+    let len = len.trim();
+    let unit_start = len
+        .find(|c: char| c.is_ascii_alphabetic() || c == '\\')
+        .unwrap_or(len.len());
+    let (value, unit) = len.split_at(unit_start);
+    let value = match value.trim() {
+        "" => 1.,
+        value => value.parse().ok()?,
+    };
+    let unit = match unit.trim() {
+        "\\textwidth" | "\\linewidth" | "\\columnwidth" | "\\hsize" => TEXT_WIDTH,
+        "\\textheight" | "\\vsize" => TEXT_HEIGHT,
+        "pt" => 96. / 72.27,
+        "bp" => 96. / 72.,
+        "in" => 96.,
+        "cm" => 96. / 2.54,
+        "mm" => 96. / 25.4,
+        // of the 10pt font
+        "em" => 10. * 96. / 72.27,
+        "px" => 1.,
+        _ => return None,
+    };
+    Some(value * unit)
+}
+
+#[test]
+fn test_header() {
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend([0, 0, 1, 0, 0, 0, 0, 0x80]);
+    assert_eq!(header(&png), Some(("image/png", (256, 128))));
+    assert_eq!(header(b"GIF89a\x0a\0\x05\0"), Some(("image/gif", (10, 5))));
+    let jpeg = b"\xff\xd8\xff\xe0\0\x04ab\xff\xc0\0\x11\x08\0\x30\0\x40";
+    assert_eq!(header(jpeg), Some(("image/jpeg", (64, 48))));
+    assert_eq!(header(b"\xff\xd8\xff\xda\0\x02"), None);
+    assert_eq!(header(b"BM"), None);
+}
+
+#[test]
+fn test_tag() {
+    let image = Image {
+        mime: "image/gif",
+        data: b"GIF".to_vec(),
+        size: (200, 100),
+    };
+    let size = |options| {
+        let tag = image.tag(options);
+        tag[..tag.find(" src").unwrap()].to_string()
+    };
+    assert_eq!(size(""), r#"<img width="200" height="100""#);
+    assert_eq!(size("scale=0.5"), r#"<img width="100" height="50""#);
+    assert_eq!(
+        size(r"width=0.5\linewidth"),
+        r#"<img width="312" height="156""#
+    );
+    assert_eq!(size("height=1in"), r#"<img width="192" height="96""#);
+    assert_eq!(
+        size("width=1in, height=1in, keepaspectratio"),
+        r#"<img width="96" height="48""#
+    );
+    assert!(image
+        .tag("")
+        .ends_with(r#"src="data:image/gif;base64,R0lG">"#));
+    assert_eq!(path(r"*[scale=2] { a/b.png }c"), Some("a/b.png"));
+}
@@ -0,0 +1,500 @@
+use nom::branch::alt;
+use nom::bytes::complete::{self as bc};
+use nom::character::complete::{self as cc};
+use nom::combinator::{opt, recognize};
+use nom::sequence::{delimited, preceded};
+use nom::Parser;
+use std::borrow::Cow;
+
+use super::{
+    edits::{units, Editor, Edits},
+    error::Error,
+    parser,
+};
+
+/// A macro definition, in the form of Temml's `macros` option.
+///
+/// The number of arguments is implied by the `#n` in `body`. Optional
+/// arguments are not supported by Temml, so a default value given to
+/// `\newcommand` is dropped and the argument becomes mandatory.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) struct Macro {
+    /// The control sequence, including the backslash.
+    pub name: String,
+    pub body: String,
+}
+
+/// An environment defined by `\newenvironment`, which is expanded before the
+/// formula is sent to Temml.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) struct Environ {
+    pub name: String,
+    pub nargs: usize,
+    /// The default value of the first argument, which makes it optional.
+    pub default: Option<String>,
+    pub begin: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) enum Definition {
+    Macro(Macro),
+    Environ(Environ),
+}
+
+/// The definitions that apply to a job.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Definitions {
+    pub macros: Vec<Macro>,
+    pub environs: Vec<Environ>,
+}
+
+impl Extend<Definition> for Definitions {
+    fn extend<T: IntoIterator<Item = Definition>>(&mut self, iter: T) {
+        for def in iter {
+            match def {
+                Definition::Macro(m) => self.macros.push(m),
+                Definition::Environ(e) => self.environs.push(e),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_definition() {
+    for (input, name, body) in [
+        (r"\newcommand{\R}{\mathbb{R}}", r"\R", r"\mathbb{R}"),
+        (r"\newcommand*\R {\mathbb{R}}", r"\R", r"\mathbb{R}"),
+        (r"\renewcommand{\f}[2][x]{#1^{#2}}", r"\f", "#1^{#2}"),
+        (r"\providecommand{\,}{\;}", r"\,", r"\;"),
+        (r"\def\f#1#2{#1+#2}", r"\f", "#1+#2"),
+        (
+            r"\DeclareMathOperator{\Tr}{Tr}",
+            r"\Tr",
+            r"\operatorname{Tr}",
+        ),
+        (
+            r"\DeclareMathOperator*{\argmax}{arg\,max}",
+            r"\argmax",
+            r"\operatorname*{arg\,max}",
+        ),
+    ] {
+        assert_eq!(
+            definition(input),
+            Ok(("", Definition::Macro(Macro::new(name, body))))
+        );
+    }
+    assert!(definition(r"\newcommand{\R}").is_err());
+    assert_eq!(
+        definition(r"\newenvironment{sys}[1][x]{\left\{\begin{aligned}#1}{\end{aligned}\right.}"),
+        Ok((
+            "",
+            Definition::Environ(Environ {
+                name: "sys".into(),
+                nargs: 1,
+                default: Some("x".into()),
+                begin: r"\left\{\begin{aligned}#1".into(),
+                end: r"\end{aligned}\right.".into(),
+            })
+        ))
+    );
+}
+
+/// Parses a single `\newcommand`, `\renewcommand`, `\providecommand`, `\def`,
+/// `\DeclareMathOperator` or `\newenvironment`.
+pub(super) fn definition(input: &str) -> nom::IResult<&str, Definition> {
+    alt((
+        newcommand.map(Definition::Macro),
+        def.map(Definition::Macro),
+        declare_math_operator.map(Definition::Macro),
+        newenvironment.map(Definition::Environ),
+    ))(input)
+}
+
+fn newcommand(input: &str) -> nom::IResult<&str, Macro> {
+    let (input, _) = alt((
+        bc::tag("\\newcommand"),
+        bc::tag("\\renewcommand"),
+        bc::tag("\\providecommand"),
+    ))
+    .and(opt(cc::char('*')))
+    .parse(input)?;
+    let (input, name) = preceded(cc::multispace0, macro_name)(input)?;
+    // the number of arguments, and the default value of the first one
+    let (input, _) = preceded(cc::multispace0, opt(bracket_group))
+        .and(preceded(cc::multispace0, opt(bracket_group)))
+        .parse(input)?;
+    let (input, body) = preceded(cc::multispace0, group)(input)?;
+    Ok((input, Macro::new(name, body)))
+}
+
+fn def(input: &str) -> nom::IResult<&str, Macro> {
+    let (input, _) = alt((bc::tag("\\def"), bc::tag("\\gdef")))(input)?;
+    let (input, name) = preceded(cc::multispace0, control_sequence)(input)?;
+    // the parameter text like `#1#2`, implied by the body
+    let (input, _) = bc::take_till(|c| c == '{')(input)?;
+    let (input, body) = group(input)?;
+    Ok((input, Macro::new(name, body)))
+}
+
+fn declare_math_operator(input: &str) -> nom::IResult<&str, Macro> {
+    let (input, star) = preceded(bc::tag("\\DeclareMathOperator"), opt(cc::char('*')))(input)?;
+    let (input, name) = preceded(cc::multispace0, macro_name)(input)?;
+    let (input, text) = preceded(cc::multispace0, group)(input)?;
+    let body = format!(
+        "\\operatorname{}{{{}}}",
+        if star.is_some() { "*" } else { "" },
+        text
+    );
+    Ok((input, Macro::new(name, &body)))
+}
+
+fn newenvironment(input: &str) -> nom::IResult<&str, Environ> {
+    let (input, _) = alt((bc::tag("\\newenvironment"), bc::tag("\\renewenvironment")))
+        .and(opt(cc::char('*')))
+        .parse(input)?;
+    let (input, name) = preceded(cc::multispace0, group)(input)?;
+    let (input, (nargs, default)) = preceded(
+        cc::multispace0,
+        opt(nom::combinator::map_res(bracket_group, |n| {
+            n.trim().parse()
+        })),
+    )
+    .and(preceded(cc::multispace0, opt(bracket_group)))
+    .parse(input)?;
+    let (input, begin) = preceded(cc::multispace0, group)(input)?;
+    let (input, end) = preceded(cc::multispace0, group)(input)?;
+    let environ = Environ {
+        name: name.trim().into(),
+        nargs: nargs.unwrap_or(0),
+        default: default.map(Into::into),
+        begin: begin.into(),
+        end: end.into(),
+    };
+    Ok((input, environ))
+}
+
+impl Macro {
+    fn new(name: &str, body: &str) -> Macro {
+        Macro {
+            name: name.into(),
+            body: body.into(),
+        }
+    }
+}
+
+/// Parses `{\name}` or `\name`.
+fn macro_name(input: &str) -> nom::IResult<&str, &str> {
+    alt((
+        delimited(
+            cc::char('{').and(cc::multispace0),
+            control_sequence,
+            cc::multispace0.and(cc::char('}')),
+        ),
+        control_sequence,
+    ))(input)
+}
+
+/// Parses a control sequence like `\name` or `\,`.
+pub(super) fn control_sequence(input: &str) -> nom::IResult<&str, &str> {
+    recognize(cc::char('\\').and(alt((cc::alpha1, recognize(cc::anychar)))))(input)
+}
+
+/// Parses a brace group, and returns its content.
+pub(super) fn group(input: &str) -> nom::IResult<&str, &str> {
+    delimited(cc::char('{'), group_content, cc::char('}'))(input)
+}
+
+fn group_content(input: &str) -> nom::IResult<&str, &str> {
+    use nom::multi::many0_count;
+    recognize(many0_count(alt((
+        recognize(cc::char('\\').and(cc::anychar)),
+        recognize(delimited(cc::char('{'), group_content, cc::char('}'))),
+        recognize(cc::none_of("{}")),
+    ))))(input)
+}
+
+fn bracket_group(input: &str) -> nom::IResult<&str, &str> {
+    delimited(cc::char('['), bc::is_not("]"), cc::char(']'))(input)
+}
+
+/// The commands that start a definition.
+const COMMANDS: [&str; 8] = [
+    "\\newcommand",
+    "\\renewcommand",
+    "\\providecommand",
+    "\\def",
+    "\\gdef",
+    "\\DeclareMathOperator",
+    "\\newenvironment",
+    "\\renewenvironment",
+];
+
+/// Collects the definitions in a preamble file. Other commands, such as
+/// `\usepackage`, are ignored.
+pub(super) fn preamble(source: &str) -> Result<Definitions, Error> {
+    let tex = parser::blank_comments(source);
+    let (rest, defs) = extract(&tex);
+    // the definitions that parse are blanked, so any command left is malformed
+    let mut i = 0;
+    while let Some(j) = rest[i..].find('\\') {
+        i += j;
+        let Ok((_, cs)) = control_sequence(&rest[i..]) else {
+            break;
+        };
+        if COMMANDS.contains(&cs) {
+            // blanks keep the number of characters but not of bytes
+            let n = rest[..i].chars().count();
+            let offset = source
+                .char_indices()
+                .nth(n)
+                .map_or(source.len(), |(k, _)| k);
+            return Err(Error::bad_input(
+                source,
+                offset,
+                format!("malformed definition `{}` in preamble", cs),
+            ));
+        }
+        i += cs.len();
+    }
+    let mut res = Definitions::default();
+    res.extend(defs);
+    Ok(res)
+}
+
+#[test]
+fn test_preamble() {
+    let defs = preamble(
+        "\\usepackage{amsmath}\n% \\def\\x{\n\\newcommand\\R{\\mathbb{R}}\n\\def\\C{\\mathbb{C}}\n",
+    )
+    .unwrap();
+    assert_eq!(
+        defs.macros,
+        vec![
+            Macro::new(r"\R", r"\mathbb{R}"),
+            Macro::new(r"\C", r"\mathbb{C}")
+        ]
+    );
+    let e = preamble("% ünïcode\n\\def\\a{1}\n\\newcommand{\\b}{2").unwrap_err();
+    let loc = e.location().unwrap();
+    assert_eq!((loc.line, loc.column), (3, 1));
+}
+
+#[test]
+fn test_extract() {
+    let (tex, macros) = extract(r"\def\a{1}\a + \\newcommand{\b}{2}\b");
+    assert_eq!(tex, r"         \a + \\newcommand{\b}{2}\b");
+    assert_eq!(macros, vec![Definition::Macro(Macro::new(r"\a", "1"))]);
+}
+
+/// Collects the definitions in a formula, and replaces them with spaces.
+pub(super) fn extract(tex: &str) -> (Cow<'_, str>, Vec<Definition>) {
+    let mut macros = vec![];
+    let mut blanks = vec![];
+    let mut i = 0;
+    while let Some(j) = tex[i..].find('\\') {
+        i += j;
+        match definition(&tex[i..]) {
+            Ok((rest, m)) => {
+                let end = tex.len() - rest.len();
+                macros.push(m);
+                blanks.push(i..end);
+                i = end;
+            }
+            // skip the escaped character, e.g. the second backslash of `\\`
+            Err(_) => {
+                i += 1;
+                i += tex[i..]
+                    .chars()
+                    .next()
+                    .map_or(0, char::len_utf8);
+            }
+        }
+    }
+    if blanks.is_empty() {
+        return (tex.into(), macros);
+    }
+    let mut res = String::from(tex);
+    for range in blanks.into_iter().rev() {
+        let n = tex[range.clone()].chars().count();
+        res.replace_range(range, &" ".repeat(n));
+    }
+    (res.into(), macros)
+}
+
+#[test]
+fn test_expand() {
+    let (_, Definition::Environ(sys)) =
+        definition(r"\newenvironment{sys}[2][0]{\left\{\begin{aligned}#1#2}{\end{aligned}\right.}")
+            .unwrap()
+    else {
+        unreachable!()
+    };
+    let (_, Definition::Environ(outer)) =
+        definition(r"\newenvironment{outer}{\begin{sys}{y}}{\end{sys}}").unwrap()
+    else {
+        unreachable!()
+    };
+    let (_, Definition::Environ(pt)) = definition(r"\newenvironment{pt}[1]{(#1,}{)}").unwrap()
+    else {
+        unreachable!()
+    };
+    let envs = [sys, outer, pt];
+    assert_eq!(
+        expand(r"\begin{pt}{x}y\end{pt}", &envs)
+            .unwrap()
+            .0,
+        "(x,y)"
+    );
+    assert_eq!(
+        expand(r"\begin{sys}[a]{b}x\\\end{sys}", &envs)
+            .unwrap()
+            .0,
+        r"\left\{\begin{aligned}abx\\\end{aligned}\right."
+    );
+    let source = r"\begin{outer}x\bad\end{ outer}";
+    let (tex, edits) = expand(source, &envs).unwrap();
+    assert_eq!(tex, r"\left\{\begin{aligned}0yx\bad\end{aligned}\right.");
+    assert_eq!(edits.origin(tex.find(r"\bad").unwrap()), 14);
+    assert_eq!(
+        expand(r"\begin{sys}\end{sys}", &envs)
+            .unwrap()
+            .0,
+        r"\begin{sys}\end{aligned}\right."
+    );
+    for def in [
+        r"\newenvironment{a}{\begin{a}\begin{a}}{\end{a}}",
+        r"\newenvironment{a}{\begin{a}}{\end{a}}",
+    ] {
+        let (_, Definition::Environ(a)) = definition(def).unwrap() else {
+            unreachable!()
+        };
+        let e = expand(r"x^2 \begin{a}y\end{a}", &[a]).unwrap_err();
+        assert_eq!((e.name.as_str(), e.pos), ("a", 4));
+    }
+}
+
+/// The number of rounds of expansion, and the length of the result, beyond
+/// which the definitions are taken as recursive.
+const MAX_ROUNDS: usize = 100;
+const MAX_LEN: usize = 1 << 20;
+
+/// A user environment whose expansion does not end.
+#[derive(Debug)]
+pub(super) struct Runaway {
+    pub name: String,
+    /// The position of its `\begin` in the formula, in UTF-16 units.
+    pub pos: usize,
+}
+
+/// Replaces `\begin{name}` and `\end{name}` of user environments with their
+/// definitions, until none is left.
+///
+/// An environment missing its arguments is left as is, so that Temml reports
+/// it in place.
+pub(super) fn expand<'a>(
+    tex: &'a str,
+    environs: &[Environ],
+) -> Result<(Cow<'a, str>, Edits), Runaway> {
+    let mut tex = Cow::from(tex);
+    let mut edits = Edits::default();
+    let mut rounds = 0;
+    while let Some((res, round, (pos, name))) = expand_once(&tex, environs) {
+        rounds += 1;
+        if rounds > MAX_ROUNDS || res.len() > MAX_LEN {
+            return Err(Runaway {
+                name: name.into(),
+                pos: edits.origin(pos),
+            });
+        }
+        tex = res.into();
+        edits.append(round);
+    }
+    Ok((tex, edits))
+}
+
+/// Expands the environments once, and returns the result, along with the
+/// position of the first environment expanded and its name.
+fn expand_once<'e>(
+    tex: &str,
+    environs: &'e [Environ],
+) -> Option<(String, Edits, (usize, &'e str))> {
+    let find = |cmd, input| {
+        let (rest, name) = parser::environ_name(input, cmd).ok()?;
+        let env = environs
+            .iter()
+            .rev()
+            .find(|e| e.name == name.0)?;
+        Some((rest, env))
+    };
+    let mut editor = Editor::new(tex);
+    let mut first = None;
+    let mut i = 0;
+    while let Some(j) = tex[i..].find('\\') {
+        i += j;
+        let expansion = match find("\\begin{", &tex[i..]) {
+            Some((rest, env)) => begin_args(rest, env)
+                .map(|(rest, args)| (rest, env.name.as_str(), substitute(&env.begin, &args))),
+            None => find("\\end{", &tex[i..])
+                .map(|(rest, env)| (rest, env.name.as_str(), env.end.clone())),
+        };
+        match expansion {
+            Some((rest, name, code)) => {
+                first.get_or_insert_with(|| (units(&tex[..i]), name));
+                let end = tex.len() - rest.len();
+                editor.replace(i..end, &code);
+                i = end;
+            }
+            // skip the escaped character, e.g. the second backslash of `\\`
+            None => {
+                i += 1;
+                i += tex[i..]
+                    .chars()
+                    .next()
+                    .map_or(0, char::len_utf8);
+            }
+        }
+    }
+    match editor.finish() {
+        (Cow::Owned(res), edits) => Some((res, edits, first?)),
+        (Cow::Borrowed(_), _) => None,
+    }
+}
+
+/// Parses the arguments following `\begin{name}`.
+fn begin_args<'a>(mut input: &'a str, env: &'a Environ) -> Option<(&'a str, Vec<&'a str>)> {
+    let mut args = vec![];
+    for k in 0..env.nargs {
+        input = input.trim_start();
+        let (rest, arg) = match &env.default {
+            Some(default) if k == 0 => bracket_group(input).unwrap_or((input, default)),
+            _ => group(input).ok()?,
+        };
+        args.push(arg);
+        input = rest;
+    }
+    Some((input, args))
+}
+
+/// Replaces `#n` in `body` with the n-th argument.
+fn substitute(body: &str, args: &[&str]) -> String {
+    let mut res = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '#' {
+            res.push(c);
+            continue;
+        }
+        match chars
+            .peek()
+            .and_then(|d| d.to_digit(10))
+        {
+            Some(n) if n >= 1 && n as usize <= args.len() => {
+                chars.next();
+                res.push_str(args[n as usize - 1]);
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
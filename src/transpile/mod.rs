@@ -1,37 +1,138 @@
+mod bibliography;
 mod comm;
+mod edits;
+mod environs;
 mod error;
+mod images;
+mod macros;
+mod numbering;
 mod parser;
 mod postproc;
+mod symbols;
+mod tabular;
+mod text;
+mod theorems;
 mod util;
 mod worker;
 mod wrap_html;
 
-pub use error::Error;
-use std::{result::Result as stdResult, thread::JoinHandle};
+pub use environs::{EnvironKind, Environs};
+pub use error::{Error, Location};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    result::Result as stdResult,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
 
 use self::{postproc::Piece, wrap_html::wrap_html};
 
+#[derive(Debug, Default)]
 pub struct Job {
     pub italic_math: bool,
+    /// Keep the spaces and line breaks of text, which HTML collapses.
     pub preserve_spaces: bool,
+    /// Lay out text as LaTeX does, where a blank line starts a paragraph and
+    /// `\\` breaks the line. Takes precedence over `preserve_spaces`.
+    pub latex_layout: bool,
+    /// Number sections, subsections and subsubsections like `1.2.3`.
+    pub number_sections: bool,
+    /// The number of equations before the snippet, which the numbering of
+    /// equations continues from.
+    pub equation_offset: usize,
+    /// Number equations by Word fields, so that Word renumbers them as they
+    /// move, instead of by static text.
+    pub equation_fields: bool,
+    pub citation_style: CitationStyle,
+    /// The directory which the paths of `\includegraphics` are relative to,
+    /// the working directory if empty.
+    pub base_dir: PathBuf,
+    /// Render failed formulas as placeholders instead of failing the whole job.
+    pub tolerant: bool,
+    /// Keep `%` comments as hidden HTML comments instead of dropping them.
+    pub keep_comments: bool,
+    pub format: Format,
     pub tex_code: String,
+    pub cancel: CancelHandle,
 }
 
-pub type Result = stdResult<String, Error>;
+/// A handle to abort a [`Job`], e.g. when it is superseded by a newer one.
+///
+/// A cancelled job sends no more formulas to the workers, and
+/// [`Solver::solve`] returns [`Error::Cancelled`] for it.
+#[derive(Debug, Default, Clone)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The flavour of [`Solver::solve`] output.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+    /// A complete CF_HTML document, ready for the Windows clipboard.
+    #[default]
+    CfHtml,
+    /// The bare HTML fragment, without the CF_HTML header and wrapping document.
+    Fragment,
+    /// The OMML of each formula, one per line.
+    Omml,
+}
+
+/// How citations are labeled, see [`Solver::set_bibliography`].
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum CitationStyle {
+    /// `[1]`, numbered in the order of citation.
+    #[default]
+    Numeric,
+    /// `(Knuth, 1984)`, with the references sorted by authors.
+    AuthorYear,
+}
+
+#[derive(Debug)]
+pub struct Output {
+    pub content: String,
+    /// Errors of the formulas rendered as placeholders, see [`Job::tolerant`].
+    pub errors: Vec<Error>,
+}
+
+pub type Result = stdResult<Output, Error>;
 
 pub struct Solver {
     jhs: Vec<JoinHandle<stdResult<(), rq::Error>>>,
     reqch: ac::Sender<Option<comm::Request>>,
     respch: ac::Receiver<comm::Response>,
+    job_id: Cell<u64>,
+    environs: Environs,
+    preamble: macros::Definitions,
+    bibliography: bibliography::Bibliography,
 }
 
 impl Drop for Solver {
     fn drop(&mut self) {
-        for _ in 0..self.jhs.len() {
-            self.reqch
-                .send_blocking(None)
-                .expect("fail to send shutdown signal");
+        let mut n_signals = self.jhs.len();
+        while n_signals > 0 {
+            match self.reqch.try_send(None) {
+                Ok(()) => n_signals -= 1,
+                // stale responses may keep the workers from picking up more requests
+                Err(ac::TrySendError::Full(_)) => {
+                    let _ = self.respch.recv_blocking();
+                }
+                Err(ac::TrySendError::Closed(_)) => panic!("fail to send shutdown signal"),
+            }
         }
+        // returns once every worker exits and drops its sender
+        while self.respch.recv_blocking().is_ok() {}
         for jh in self.jhs.drain(..) {
             jh.join()
                 .unwrap()
@@ -42,8 +143,10 @@ impl Drop for Solver {
 
 impl Solver {
     pub fn new(nworkers: usize) -> Solver {
-        let (reqch_s, reqch_r) = ac::bounded(4);
-        let (respch_s, respch_r) = ac::bounded(4);
+        // Deep enough to keep every worker busy while the responses are consumed.
+        let cap = 2 * nworkers.max(1);
+        let (reqch_s, reqch_r) = ac::bounded(cap);
+        let (respch_s, respch_r) = ac::bounded(cap);
         let jhs = (0..nworkers)
             .map(|_| worker::run(reqch_r.clone(), respch_s.clone()))
             .collect();
@@ -51,51 +154,451 @@ impl Solver {
             reqch: reqch_s,
             respch: respch_r,
             jhs,
+            job_id: Default::default(),
+            environs: Default::default(),
+            preamble: Default::default(),
+            bibliography: Default::default(),
         }
     }
 
-    fn solve_math_part<R: Default>(
+    /// The environments recognized in [`Job::tex_code`].
+    pub fn environs_mut(&mut self) -> &mut Environs {
+        &mut self.environs
+    }
+
+    /// Replaces the macros and environments shared by every job with the
+    /// definitions in `tex`.
+    pub fn set_preamble(&mut self, tex: &str) -> stdResult<(), Error> {
+        self.preamble = macros::preamble(tex)?;
+        Ok(())
+    }
+
+    /// Loads the preamble from a file, see [`Solver::set_preamble`].
+    pub fn load_preamble<P: AsRef<Path>>(&mut self, path: P) -> stdResult<(), Error> {
+        self.set_preamble(&read(path.as_ref())?)
+    }
+
+    /// Replaces the entries which `\cite` refers to with those in `source`,
+    /// in BibTeX or CSL-JSON.
+    pub fn set_bibliography(&mut self, source: &str) -> stdResult<(), Error> {
+        self.bibliography = bibliography::parse(source)?;
+        Ok(())
+    }
+
+    /// Loads the bibliography from a file, see [`Solver::set_bibliography`].
+    pub fn load_bibliography<P: AsRef<Path>>(&mut self, path: P) -> stdResult<(), Error> {
+        self.set_bibliography(&read(path.as_ref())?)
+    }
+
+    fn solve_math_part<'a>(
         &self,
-        part: parser::Part,
+        part: &parser::Part<'a>,
         loc: usize,
-        counter: &mut usize,
-    ) -> R {
-        self.reqch
-            .send_blocking(Some(comm::Request {
-                loc,
-                tex: String::from(part.as_str()),
-                display_mode: part.typ != parser::Type::InlineMath,
-            }))
-            .unwrap();
-        *counter += 1;
-        Default::default()
+        reqs: &mut Vec<comm::Request>,
+        macros: &Arc<Vec<macros::Macro>>,
+        environs: &[macros::Environ],
+        numbering: &numbering::Numbering,
+    ) -> stdResult<edits::Edits, macros::Runaway> {
+        // blanks keep the positions in Temml error messages
+        let tex = parser::blank_comments(part.as_str());
+        // definitions have been collected for every formula
+        let (tex, _) = macros::extract(&tex);
+        let (tex, mut edits) = macros::expand(&tex, environs)?;
+        let (tex, stripped) = numbering::strip(&tex, numbering);
+        edits.append(stripped);
+        reqs.push(comm::Request {
+            job: self.job_id.get(),
+            loc,
+            tex: tex.into_owned(),
+            display_mode: part.typ != parser::Type::InlineMath,
+            macros: macros.clone(),
+        });
+        Ok(edits)
+    }
+
+    /// Feeds `reqs` to the workers and passes each response to `on_response`.
+    ///
+    /// Requests are only sent while the request channel has room, and we fall
+    /// back to draining the response channel otherwise, so that neither side
+    /// can block the other regardless of how many requests there are.
+    ///
+    /// Responses left over by previous jobs, which returned early on error or
+    /// cancellation, are recognized by their job id and dropped.
+    fn dispatch<F>(
+        &self,
+        reqs: Vec<comm::Request>,
+        cancel: &CancelHandle,
+        mut on_response: F,
+    ) -> stdResult<(), Error>
+    where
+        F: FnMut(comm::Response) -> stdResult<(), Error>,
+    {
+        let job_id = self.job_id.get();
+        let mut n_pending = reqs.len();
+        let mut reqs = reqs.into_iter();
+        let mut next = reqs.next();
+        while n_pending > 0 {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            while let Some(req) = next.take() {
+                match self.reqch.try_send(Some(req)) {
+                    Ok(()) => next = reqs.next(),
+                    Err(ac::TrySendError::Full(req)) => {
+                        next = req;
+                        break;
+                    }
+                    Err(ac::TrySendError::Closed(_)) => panic!("request channel closed"),
+                }
+            }
+            let resp = self.respch.recv_blocking().unwrap();
+            if resp.job != job_id {
+                continue;
+            }
+            n_pending -= 1;
+            on_response(resp)?;
+        }
+        Ok(())
     }
 
     pub fn solve(&self, job: Job) -> Result {
-        let mut n_maths = 0;
-        let mut pieces = parser::parse(&job.tex_code)
-            .map_err(Error::bad_input)?
-            .into_iter()
-            .enumerate()
-            .map(|(i, p)| match p.typ {
+        if job.cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        self.job_id
+            .set(self.job_id.get().wrapping_add(1));
+        let source = job.tex_code.as_str();
+        // definitions anywhere in the snippet apply to all the formulas, and
+        // the environments defined are parsed as math
+        let mut defs = self.preamble.clone();
+        defs.extend(macros::extract(&parser::blank_comments(source)).1);
+        let mut environs = self.environs.clone();
+        for e in &defs.environs {
+            environs.insert(e.name.as_str(), EnvironKind::Math);
+        }
+        let macros = Arc::new(defs.macros);
+        let mut parts = parser::parse(source, &environs);
+        parts.retain(|p| match p.typ {
+            parser::Type::Definition => false,
+            parser::Type::Comment => job.keep_comments,
+            _ => true,
+        });
+        let mut errors = vec![];
+        for p in parts
+            .iter()
+            .filter(|p| p.typ == parser::Type::Error)
+        {
+            let e = Error::bad_input(source, p.span.start, p.error_message());
+            if !job.tolerant {
+                return Err(e);
+            }
+            errors.push(e);
+        }
+        // the images are left out of OMML
+        let (images, image_errors) = match job.format {
+            Format::Omml => Default::default(),
+            _ => images::load(source, &parts, &job.base_dir),
+        };
+        for e in image_errors {
+            if !job.tolerant {
+                return Err(e);
+            }
+            errors.push(e);
+        }
+        let numbering = numbering::number(&parts, job.equation_offset);
+        let mut reqs = vec![];
+        // by which the errors of the formulas are located, by their indices
+        let mut part_edits = HashMap::new();
+        let mut pieces = Vec::with_capacity(parts.len());
+        for (i, p) in parts.iter().enumerate() {
+            pieces.push(match p.typ {
                 parser::Type::Text => Piece::from_text(p.as_str()),
-                _ => self.solve_math_part(p, i, &mut n_maths),
-            })
-            .collect::<Vec<_>>();
-        for _ in 0..n_maths {
-            let comm::Response { omml, loc } = self.respch.recv_blocking().unwrap();
-            let omml = omml.map_err(Error::JS)?;
-            pieces[loc] = postproc::Piece::from_math(omml.into());
+                parser::Type::Error => Piece::from_failed(p.as_str()),
+                parser::Type::Comment => Piece::from_comment(p.as_str()),
+                _ => {
+                    match self.solve_math_part(p, i, &mut reqs, &macros, &defs.environs, &numbering)
+                    {
+                        Ok(e) => {
+                            part_edits.insert(i, e);
+                        }
+                        Err(macros::Runaway { name, pos }) => {
+                            let offset = p.span.start + edits::byte_offset(p.as_str(), pos);
+                            let msg = format!("environment `{}` expands without end", name);
+                            let e = Error::bad_input(source, offset, msg);
+                            if !job.tolerant {
+                                return Err(e);
+                            }
+                            errors.push(e);
+                        }
+                    }
+                    // stays in place if the formula fails to render
+                    Piece::from_failed(p.as_str())
+                }
+            });
         }
+        self.dispatch(reqs, &job.cancel, |comm::Response { omml, loc, .. }| {
+            match omml {
+                Ok(omml) => pieces[loc] = postproc::Piece::from_math(omml.into()),
+                Err(e) => {
+                    let e = Error::js(source, parts[loc].span.clone(), &part_edits[&loc], e);
+                    if !job.tolerant {
+                        return Err(e);
+                    }
+                    errors.push(e)
+                }
+            }
+            Ok(())
+        })?;
 
-        #[allow(clippy::map_flatten)]
-        Ok(wrap_html(
-            pieces
+        if job.format == Format::Omml {
+            let content = pieces
                 .into_iter()
-                .map(postproc::italic_math(job.italic_math))
-                .map(postproc::escape_html)
-                .map(postproc::preserve_spaces(job.preserve_spaces))
-                .flatten(),
-        ))
+                .filter(Piece::is_math)
+                .map(Piece::into_content)
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Ok(Output { content, errors });
+        }
+
+        #[allow(clippy::map_flatten)]
+        let codes = text::render(
+            &parts,
+            pieces,
+            &job,
+            &numbering,
+            &self.bibliography,
+            &images,
+        )
+        .into_iter()
+        .map(postproc::italic_math(job.italic_math))
+        .map(postproc::escape_html)
+        .flatten();
+        let content = match job.format {
+            Format::CfHtml => wrap_html(codes),
+            _ => codes.collect(),
+        };
+        Ok(Output { content, errors })
     }
 }
+
+fn read(path: &Path) -> stdResult<String, Error> {
+    std::fs::read_to_string(path).map_err(|e| {
+        Error::BadInput(
+            format!("fail to read {}: {}", path.display(), e).into(),
+            None,
+        )
+    })
+}
+
+#[test]
+fn test_solve_many_formulas() {
+    use std::{sync::mpsc, time::Duration};
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let solver = Solver::new(2);
+        let tex_code = (0..500)
+            .map(|i| format!("${}$ ", i))
+            .collect();
+        tx.send(solver.solve(Job {
+            format: Format::Omml,
+            tex_code,
+            ..Default::default()
+        }))
+        .unwrap();
+    });
+    let output = rx
+        .recv_timeout(Duration::from_secs(60))
+        .expect("solver hangs")
+        .unwrap();
+    assert_eq!(output.content.lines().count(), 500);
+}
+
+#[test]
+fn test_solve_after_failure() {
+    let solver = Solver::new(2);
+    let tex_code = String::from(r"$\frac{$") + &"$x$".repeat(20);
+    assert!(solver
+        .solve(Job {
+            tex_code,
+            ..Default::default()
+        })
+        .is_err());
+    let output = solver
+        .solve(Job {
+            format: Format::Omml,
+            tex_code: "$y$ $z$".into(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(output.content.lines().count(), 2);
+}
+
+#[test]
+fn test_solve_tolerant() {
+    let solver = Solver::new(2);
+    let output = solver
+        .solve(Job {
+            tolerant: true,
+            format: Format::Fragment,
+            tex_code: r"$x$ and $\frac{<$ and $y$".into(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(output.errors.len(), 1);
+    assert!(output
+        .content
+        .contains(r#"<span style="color:red">\frac{&lt;</span>"#));
+    assert_eq!(
+        output
+            .content
+            .matches("<m:oMath")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_solve_recovery() {
+    let solver = Solver::new(1);
+    let job = |tolerant| Job {
+        tolerant,
+        format: Format::Fragment,
+        tex_code: "$x$ and\n$y".into(),
+        ..Default::default()
+    };
+    let e = solver.solve(job(false)).unwrap_err();
+    assert!(matches!(e, Error::BadInput(..)));
+    assert_eq!(e.location().map(|l| (l.line, l.column)), Some((2, 1)));
+    let output = solver.solve(job(true)).unwrap();
+    assert_eq!(output.errors.len(), 1);
+    assert!(output
+        .content
+        .ends_with(r#"<span style="color:red">$</span>y"#));
+}
+
+#[test]
+fn test_solve_macros() {
+    let solver = Solver::new(2);
+    let output = solver
+        .solve(Job {
+            format: Format::Fragment,
+            tex_code: r"\newcommand{\R}{\mathbb{R}}$x\in\R$ and $\def\N{\mathbb{N}}\N\subset\R$"
+                .into(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(!output.content.contains("newcommand"));
+    assert_eq!(
+        output
+            .content
+            .matches("<m:oMath")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_solve_preamble() {
+    let mut solver = Solver::new(1);
+    assert!(solver
+        .set_preamble(r"\newcommand{\R}{\mathbb")
+        .is_err());
+    solver
+        .set_preamble(r"\newcommand{\R}{\mathbb{R}}")
+        .unwrap();
+    for _ in 0..2 {
+        let job = Job {
+            tex_code: r"$x\in\R$".into(),
+            ..Default::default()
+        };
+        assert!(solver.solve(job).is_ok());
+    }
+}
+
+#[test]
+fn test_solve_environs() {
+    let mut solver = Solver::new(1);
+    solver
+        .set_preamble(r"\newenvironment{sys}{\left[\begin{aligned}}{\end{aligned}\right.}")
+        .unwrap();
+    let output = solver
+        .solve(Job {
+            format: Format::Fragment,
+            tex_code:
+                r"\newenvironment{pt}[1]{(#1,}{)}\begin{sys}x&=1\end{sys} at \begin{pt}{x}y\end{pt}"
+                    .into(),
+            ..Default::default()
+        })
+        .unwrap();
+    // Temml fails on the environments unless they are expanded
+    assert!(!output
+        .content
+        .contains("newenvironment"));
+    assert_eq!(
+        output
+            .content
+            .matches("<m:oMath")
+            .count(),
+        2
+    );
+    assert!(output.content.contains(" at "));
+}
+
+#[test]
+fn test_solve_runaway_environ() {
+    let solver = Solver::new(1);
+    let job = |tolerant| Job {
+        tolerant,
+        tex_code: r"\newenvironment{a}{\begin{a}\begin{a}}{\end{a}}$\begin{a}x\end{a}$".into(),
+        ..Default::default()
+    };
+    let e = solver.solve(job(false)).unwrap_err();
+    assert!(matches!(e, Error::BadInput(..)));
+    assert_eq!(e.location().map(|l| l.column), Some(49));
+    assert_eq!(
+        solver
+            .solve(job(true))
+            .unwrap()
+            .errors
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_solve_images() {
+    let dir = std::env::temp_dir().join("textocx-test-images");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dot.gif"), b"GIF89a\x02\0\x01\0").unwrap();
+    let solver = Solver::new(1);
+    let job = |tex_code: &str| Job {
+        format: Format::Fragment,
+        base_dir: dir.clone(),
+        tex_code: tex_code.into(),
+        ..Default::default()
+    };
+    let output = solver
+        .solve(job(r"\includegraphics[scale=2]{dot}"))
+        .unwrap();
+    assert_eq!(
+        output.content,
+        r#"<img width="4" height="2" src="data:image/gif;base64,R0lGODlhAgABAA==">"#
+    );
+    let e = solver
+        .solve(job(r"$x$ \includegraphics{missing.png}"))
+        .unwrap_err();
+    assert!(matches!(e, Error::BadInput(..)));
+    assert_eq!(e.location().map(|l| l.column), Some(5));
+}
+
+#[test]
+fn test_solve_cancelled() {
+    let solver = Solver::new(1);
+    let job = Job {
+        tex_code: "$x$".into(),
+        ..Default::default()
+    };
+    job.cancel.cancel();
+    assert!(matches!(solver.solve(job), Err(Error::Cancelled)));
+}
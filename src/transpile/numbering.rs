@@ -0,0 +1,326 @@
+use std::{borrow::Cow, collections::HashMap, ops::Range};
+
+use super::edits::{Editor, Edits};
+use super::macros;
+use super::parser::{self, EnvironName, Part, Type};
+
+/// The environments numbered by default, unless starred.
+const NUMBERED: &[&str] = &["equation", "align", "alignat", "gather", "multline"];
+
+/// The environments whose lines are numbered one by one.
+const MULTILINE: &[&str] = &["align", "alignat", "gather"];
+
+/// The number of an equation.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) enum Number {
+    /// A number from the counter of equations.
+    Auto(usize),
+    /// A number given by `\tag`, and whether it is shown in parentheses, as
+    /// opposed to `\tag*`.
+    Tag(String, bool),
+}
+
+impl Number {
+    /// The number as shown beside the equation.
+    pub fn shown(&self) -> String {
+        match self {
+            Number::Tag(tag, false) => tag.clone(),
+            _ => format!("({})", self.referred()),
+        }
+    }
+    /// The number as given by `\ref`.
+    pub fn referred(&self) -> String {
+        match self {
+            Number::Auto(n) => n.to_string(),
+            Number::Tag(tag, _) => tag.clone(),
+        }
+    }
+}
+
+/// The numbers of the display equations in a snippet.
+#[derive(Debug, Default)]
+pub(super) struct Numbering {
+    /// The numbers of the lines of each numbered formula, by the index of
+    /// its part.
+    pub equations: HashMap<usize, Vec<Option<Number>>>,
+    pub labels: HashMap<String, Number>,
+}
+
+impl Numbering {
+    /// The number of `label` as given by `\ref`, or `??` if it is unknown.
+    pub fn referred(&self, label: &str) -> String {
+        self.labels
+            .get(label.trim())
+            .map_or("??".into(), Number::referred)
+    }
+    /// The labels on the equation numbered `n` by the counter.
+    pub fn labels_of(&self, n: usize) -> Vec<&str> {
+        let mut labels = self
+            .labels
+            .iter()
+            .filter(|(_, number)| **number == Number::Auto(n))
+            .map(|(label, _)| label.as_str())
+            .collect::<Vec<_>>();
+        labels.sort();
+        labels
+    }
+}
+
+/// The name of the Word bookmark on the number of an equation labeled
+/// `label`, which is hidden as it starts with `_`.
+pub(super) fn bookmark(label: &str) -> String {
+    "_Ref_"
+        .chars()
+        .chain(
+            label
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() {
+                    true => c,
+                    false => '_',
+                }),
+        )
+        // the limit of Word
+        .take(40)
+        .collect()
+}
+
+/// Numbers the display equations in `parts`, after `offset` equations.
+pub(super) fn number(parts: &[Part], offset: usize) -> Numbering {
+    let mut res = Numbering::default();
+    let mut counter = offset;
+    for (i, part) in parts.iter().enumerate() {
+        let (numbered, multiline) = match part.typ {
+            Type::BlockMath => (false, false),
+            Type::Environ => match parser::environ_name(part.as_str(), "\\begin{") {
+                Ok((_, EnvironName(name))) => (
+                    NUMBERED.contains(&name),
+                    MULTILINE.contains(&name.trim_end_matches('*')),
+                ),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        let tex = parser::blank_comments(part.as_str());
+        let lines = if multiline { lines(&tex) } else { vec![&*tex] };
+        let mut numbers = vec![];
+        for line in lines {
+            let commands = commands(line, &["\\label", "\\tag", "\\notag", "\\nonumber"]);
+            let number = match commands
+                .iter()
+                .find(|c| c.name == "\\tag")
+            {
+                Some(tag) => Some(Number::Tag(tag.arg.trim().into(), !tag.star)),
+                None if numbered
+                    && !commands
+                        .iter()
+                        .any(|c| matches!(c.name, "\\notag" | "\\nonumber")) =>
+                {
+                    counter += 1;
+                    Some(Number::Auto(counter))
+                }
+                None => None,
+            };
+            if let Some(number) = &number {
+                for label in commands
+                    .iter()
+                    .filter(|c| c.name == "\\label")
+                {
+                    res.labels
+                        .insert(label.arg.trim().into(), number.clone());
+                }
+            }
+            numbers.push(number);
+        }
+        if numbers.iter().any(Option::is_some) {
+            res.equations.insert(i, numbers);
+        }
+    }
+    res
+}
+
+/// Removes the labels and tags from a formula, as it is numbered beside, and
+/// resolves the references in it.
+pub(super) fn strip<'t>(tex: &'t str, numbering: &Numbering) -> (Cow<'t, str>, Edits) {
+    let mut editor = Editor::new(tex);
+    // Temml numbers the environment otherwise, if it spans the whole formula
+    let mut end = None;
+    if let Ok((body, EnvironName(name))) = parser::environ_name(tex, "\\begin{") {
+        let start = tex.len() - body.len();
+        let i = body
+            .rfind("\\end")
+            .unwrap_or(body.len());
+        let spans = matches!(
+            parser::environ_name(&body[i..], "\\end{"),
+            Ok((rest, EnvironName(n))) if n == name && rest.trim().is_empty()
+        );
+        if NUMBERED.contains(&name) && spans {
+            editor.replace(0..start, &format!("\\begin{{{}*}}", name));
+            end = Some((start + i, format!("\\end{{{}*}}", name)));
+        }
+    }
+    let commands = commands(
+        tex,
+        &[
+            "\\label",
+            "\\tag",
+            "\\notag",
+            "\\nonumber",
+            "\\ref",
+            "\\eqref",
+        ],
+    );
+    for c in commands {
+        let with = match c.name {
+            "\\ref" => format!("\\text{{{}}}", numbering.referred(c.arg)),
+            "\\eqref" => format!("\\text{{({})}}", numbering.referred(c.arg)),
+            _ => String::new(),
+        };
+        editor.replace(c.span, &with);
+    }
+    if let Some((i, with)) = end {
+        editor.replace(i..tex.len(), &with);
+    }
+    editor.finish()
+}
+
+#[test]
+fn test_number() {
+    let source = r"\begin{equation}a\label{a}\end{equation}
+\begin{align}
+b &= c \label{b} \\
+d &= e \notag \\
+\begin{matrix} f \\ g \end{matrix} \tag*{T} \label{t} \\
+\end{align}
+\[ h \tag{H} \] \[ i \]
+\begin{gather*} j \\ k \tag{K} \end{gather*}";
+    let parts = parser::parse(source, &Default::default());
+    let numbering = number(&parts, 2);
+    let mut equations = numbering
+        .equations
+        .iter()
+        .map(|(&i, numbers)| {
+            let numbers = numbers
+                .iter()
+                .map(|n| n.as_ref().map(Number::shown));
+            (i, numbers.collect::<Vec<_>>())
+        })
+        .collect::<Vec<_>>();
+    equations.sort();
+    assert_eq!(
+        equations,
+        vec![
+            (0, vec![Some("(3)".into())]),
+            (2, vec![Some("(4)".into()), None, Some("T".into())]),
+            (4, vec![Some("(H)".into())]),
+            (8, vec![None, Some("(K)".into())]),
+        ]
+    );
+    assert_eq!(numbering.labels["b"], Number::Auto(4));
+    assert_eq!(numbering.referred("t"), "T");
+    assert_eq!(numbering.referred("x"), "??");
+}
+
+#[test]
+fn test_strip() {
+    let numbering = Numbering {
+        labels: [("a".into(), Number::Auto(1))].into(),
+        ..Default::default()
+    };
+    let (tex, edits) = strip(
+        r"\begin{equation }x \label{a}\tag*{A}\bad\end{ equation}",
+        &numbering,
+    );
+    assert_eq!(tex, r"\begin{equation*}x \bad\end{equation*}");
+    assert_eq!(edits.origin(tex.find(r"\bad").unwrap()), 36);
+    let (tex, edits) = strip(r"x=\eqref{a}+\ref{ b }\\label", &numbering);
+    assert_eq!(tex, r"x=\text{(1)}+\text{??}\\label");
+    assert_eq!(edits.origin(tex.find('+').unwrap()), 11);
+    assert!(matches!(strip(r"\labels", &numbering).0, Cow::Borrowed(_)));
+    // the environment is left alone if commands follow it
+    let (tex, _) = strip(r"\begin{equation}x\end{equation}\label{a}", &numbering);
+    assert_eq!(tex, r"\begin{equation}x\end{equation}");
+    let (tex, _) = strip(r"\begin{equation}x\end{equation}\notag", &numbering);
+    assert_eq!(tex, r"\begin{equation}x\end{equation}");
+}
+
+/// Splits the content of an environment like `align` into lines, at the `\\`
+/// not nested in groups or environments.
+fn lines(tex: &str) -> Vec<&str> {
+    let mut res = vec![];
+    let (mut depth, mut nesting, mut start) = (0, 0, 0);
+    let mut chars = tex.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '\\' => {
+                let rest = &tex[i..];
+                if rest.starts_with("\\begin") {
+                    nesting += 1;
+                } else if rest.starts_with("\\end") {
+                    nesting -= 1;
+                } else if rest.starts_with("\\\\") && depth == 0 && nesting == 1 {
+                    res.push(&tex[start..i]);
+                    start = i + 2;
+                }
+                // the escaped character
+                chars.next();
+            }
+            _ => (),
+        }
+    }
+    res.push(&tex[start..]);
+    // a `\\` after the last line
+    if res.len() > 1
+        && res
+            .last()
+            .is_some_and(|l| l.trim_start().starts_with("\\end"))
+    {
+        res.pop();
+    }
+    res
+}
+
+/// A command found in a formula.
+struct Command<'t> {
+    name: &'t str,
+    span: Range<usize>,
+    /// Whether the command is starred, like `\tag*`.
+    star: bool,
+    /// The content of the group after the command, empty for `\notag` and
+    /// `\nonumber`.
+    arg: &'t str,
+}
+
+/// Finds the commands in `names`, with their arguments.
+fn commands<'t>(tex: &'t str, names: &[&str]) -> Vec<Command<'t>> {
+    let mut res = vec![];
+    let mut i = 0;
+    while let Some(start) = tex[i..].find('\\').map(|j| i + j) {
+        let Ok((rest, name)) = macros::control_sequence(&tex[start..]) else {
+            break;
+        };
+        i = tex.len() - rest.len();
+        if !names.contains(&name) {
+            continue;
+        }
+        let star = rest.starts_with('*');
+        if star {
+            i += 1;
+        }
+        let mut arg = "";
+        if !matches!(name, "\\notag" | "\\nonumber") {
+            if let Ok((rest, group)) = macros::group(tex[i..].trim_start()) {
+                arg = group;
+                i = tex.len() - rest.len();
+            }
+        }
+        res.push(Command {
+            name,
+            span: start..i,
+            star,
+            arg,
+        });
+    }
+    res
+}
@@ -2,7 +2,11 @@ use nom::branch::alt;
 use nom::bytes::complete::{self as bc};
 use nom::character::complete::{self as cc};
 use nom::sequence::delimited;
-use nom::{Finish, Parser};
+use nom::{Offset, Parser};
+use std::{borrow::Cow, ops::Range};
+
+use super::environs::Environs;
+use super::macros;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub(super) enum Type {
@@ -10,11 +14,29 @@ pub(super) enum Type {
     InlineMath,
     BlockMath,
     Environ,
+    /// A `%` comment in text.
+    Comment,
+    /// A definition like `\newcommand` or `\newenvironment` in text.
+    Definition,
+    /// An unmatched delimiter, skipped to recover from the error.
+    Error,
 }
 
 impl Type {
-    fn build(self, content: &str) -> Part {
-        Part { typ: self, content }
+    fn build(self, content: &str) -> Part<'_> {
+        Part {
+            typ: self,
+            content,
+            span: 0..0,
+        }
+    }
+    #[cfg(test)]
+    fn at(self, start: usize, content: &str) -> Part<'_> {
+        Part {
+            typ: self,
+            content,
+            span: start..start + content.len(),
+        }
     }
     fn builder(self) -> impl Fn(&str) -> Part {
         move |content| self.build(content)
@@ -25,62 +47,231 @@ impl Type {
 pub(super) struct Part<'a> {
     pub typ: Type,
     content: &'a str,
+    /// Byte range of the content in the parsed input.
+    pub span: Range<usize>,
 }
 
 impl<'a> Part<'a> {
     pub fn as_str(&self) -> &'a str {
         self.content
     }
+    /// Describes the problem of a [`Type::Error`] part.
+    pub fn error_message(&self) -> String {
+        if self.content.starts_with("\\end") {
+            format!("unmatched `{}`", self.content)
+        } else {
+            format!("unclosed `{}`", self.content)
+        }
+    }
 }
 
 #[test]
 fn test_parse() {
     use Type::*;
     for (input, res) in [
-        (r"123你好", Ok(vec![Text.build("123你好")])),
+        (r"123你好", vec![Text.at(0, "123你好")]),
         (
             r"123你好 $\sqrt{1}$",
-            Ok(vec![Text.build("123你好 "), InlineMath.build(r"\sqrt{1}")]),
+            vec![Text.at(0, "123你好 "), InlineMath.at(11, r"\sqrt{1}")],
         ),
         (
             r"123你好 $42$ \begin{align }你abc\end{ align}$$abc$$",
-            Ok(vec![
-                Text.build("123你好 "),
-                InlineMath.build("42"),
-                Text.build(" "),
-                Environ.build(r"\begin{align }你abc\end{ align}"),
-                BlockMath.build("abc"),
-            ]),
+            vec![
+                Text.at(0, "123你好 "),
+                InlineMath.at(11, "42"),
+                Text.at(14, " "),
+                Environ.at(15, r"\begin{align }你abc\end{ align}"),
+                BlockMath.at(49, "abc"),
+            ],
+        ),
+        (
+            r"a \(x\) b \[\frac12\] c\\[2pt]",
+            vec![
+                Text.at(0, "a "),
+                InlineMath.at(4, "x"),
+                Text.at(7, " b "),
+                BlockMath.at(12, r"\frac12"),
+                Text.at(21, r" c\\[2pt]"),
+            ],
         ),
     ] {
-        assert_eq!(parse(input), res);
+        assert_eq!(parse(input, &Default::default()), res);
     }
 }
 
-type Error<'a> = nom::error::Error<&'a str>;
+#[test]
+fn test_parse_recovery() {
+    use Type::*;
+    for (input, res) in [
+        (
+            "a $x + y",
+            vec![Text.at(0, "a "), Error.at(2, "$"), Text.at(3, "x + y")],
+        ),
+        (
+            r"\begin{align} x \end{equation}\(y",
+            vec![
+                Error.at(0, r"\begin{align}"),
+                Text.at(13, " x "),
+                Error.at(16, r"\end{equation}"),
+                Error.at(30, r"\("),
+                Text.at(32, "y"),
+            ],
+        ),
+        (
+            "$$x$ y",
+            vec![
+                Error.at(0, "$$"),
+                Text.at(2, "x"),
+                Error.at(3, "$"),
+                Text.at(4, " y"),
+            ],
+        ),
+    ] {
+        assert_eq!(parse(input, &Default::default()), res);
+    }
+}
 
-pub(super) fn parse(input: &str) -> Result<Vec<Part>, Error> {
-    nom::combinator::all_consuming(nom::multi::many0(alt((
+/// Splits `input` into parts.
+///
+/// The parser never fails. An unmatched delimiter becomes a [`Type::Error`]
+/// part, and parsing resumes right after it.
+pub(super) fn parse<'a>(input: &'a str, envs: &Environs) -> Vec<Part<'a>> {
+    let (_, parts) = nom::multi::many0(alt((
         block_math,
         inline_math,
-        environ,
-        text,
-    ))))(input)
-    .finish()
-    .map(|(_, parts)| parts)
+        |i| environ(i, envs),
+        comment,
+        definition,
+        |i| text(i, envs),
+        |i| bad_delimiter(i, envs),
+    )))(input)
+    .expect("parser should recover from any input");
+    parts
+        .into_iter()
+        .map(|p| Part {
+            span: input.offset(p.content)..input.offset(p.content) + p.content.len(),
+            ..p
+        })
+        .collect()
+}
+
+/// Recognizes the delimiter that [`text`] stops at but nothing else accepts.
+fn bad_delimiter<'a>(input: &'a str, envs: &Environs) -> nom::IResult<&'a str, Part<'a>> {
+    use nom::combinator::recognize;
+    alt((
+        recognize(|i| math_environ_name(i, "\\begin{", envs)),
+        recognize(|i| math_environ_name(i, "\\end{", envs)),
+        bc::tag("$$"),
+        bc::tag("\\("),
+        bc::tag("\\["),
+        bc::take(1usize),
+    ))(input)
+    .map_output(Type::Error.builder())
+}
+
+#[test]
+fn test_comment() {
+    use Type::*;
+    assert_eq!(
+        parse("a% x $y$\n  b \\% c %\n\nd", &Default::default()),
+        vec![
+            Text.at(0, "a"),
+            Comment.at(1, "% x $y$\n  "),
+            Text.at(11, r"b \% c "),
+            Comment.at(18, "%\n"),
+            Text.at(20, "\nd"),
+        ]
+    );
+    assert_eq!(
+        parse("$a % b $c\n$", &Default::default()),
+        vec![InlineMath.at(1, "a % b $c\n")]
+    );
+    assert_eq!(blank_comments(r"a \% b % c"), r"a \% b    ");
+}
+
+/// Recognizes a macro definition in text, which is removed from the output.
+fn definition(input: &str) -> nom::IResult<&str, Part<'_>> {
+    use nom::combinator::recognize;
+    recognize(macros::definition)(input).map_output(Type::Definition.builder())
+}
+
+#[test]
+fn test_definition() {
+    use Type::*;
+    assert_eq!(
+        parse(
+            r"\newcommand{\R}{\mathbb{R}} $\R$ \\def",
+            &Default::default()
+        ),
+        vec![
+            Definition.at(0, r"\newcommand{\R}{\mathbb{R}}"),
+            Text.at(27, " "),
+            InlineMath.at(29, r"\R"),
+            Text.at(32, r" \\def"),
+        ]
+    );
+}
+
+/// Recognizes a comment, which in text also takes the line break and the
+/// indentation of the following line, as LaTeX does.
+fn comment(input: &str) -> nom::IResult<&str, Part<'_>> {
+    use nom::combinator::{opt, recognize};
+    recognize(comment_body.and(opt(cc::line_ending.and(cc::space0))))(input)
+        .map_output(Type::Comment.builder())
+}
+
+fn comment_body(input: &str) -> nom::IResult<&str, ()> {
+    cc::char('%')
+        .and(cc::not_line_ending)
+        .map(unit)
+        .parse(input)
 }
 
-fn text(input: &str) -> nom::IResult<&str, Part> {
+/// Replaces the comments in a formula with spaces, which keeps the positions
+/// in Temml error messages valid.
+pub(super) fn blank_comments(tex: &str) -> Cow<'_, str> {
+    if !tex.contains('%') {
+        return tex.into();
+    }
+    let mut res = String::with_capacity(tex.len());
+    let mut chars = tex.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                res.push(c);
+                res.extend(chars.next());
+            }
+            '%' => {
+                res.push(' ');
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        res.push(c);
+                        break;
+                    }
+                    res.push(' ');
+                }
+            }
+            _ => res.push(c),
+        }
+    }
+    res.into()
+}
+
+fn text<'a>(input: &'a str, envs: &Environs) -> nom::IResult<&'a str, Part<'a>> {
     use nom::{combinator::*, multi::*};
     recognize(many1_count(alt((
         cc::char::<&str, nom::error::Error<&str>>('\\')
-            .and(cc::one_of(r#"\$~{}"#))
+            .and(cc::one_of(r#"\$~{}%"#))
             .map(unit),
         not(alt((
             //
             bc::tag("$"),
-            bc::tag("\\begin"),
-            bc::tag("\\end"),
+            bc::tag("%"),
+            recognize(macros::definition),
+            bc::tag("\\("),
+            bc::tag("\\["),
+            recognize(|i| math_environ_name(i, "\\begin{", envs)),
+            recognize(|i| math_environ_name(i, "\\end{", envs)),
         )))
         .and(bc::take(1usize))
         .map(unit),
@@ -92,76 +283,183 @@ fn text(input: &str) -> nom::IResult<&str, Part> {
 fn test_inline_math() {
     assert_eq!(inline_math("$1$"), Ok(("", Type::InlineMath.build("1"))));
     assert!(inline_math("$1").is_err());
+    assert_eq!(inline_math(r"\(1\)"), Ok(("", Type::InlineMath.build("1"))));
+    assert!(inline_math(r"\(1\]").is_err());
+    for input in [
+        r"$\$5$",
+        r"$x \text{ if $y$ holds}$",
+        r"$a{b$",
+        r"\(\text{\)}\)",
+    ] {
+        let (rest, part) = inline_math(input).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            part.as_str().len(),
+            input.len() - if input.starts_with('$') { 2 } else { 4 }
+        );
+    }
+    assert_eq!(
+        inline_math(r"$\$$ $"),
+        Ok((" $", Type::InlineMath.build(r"\$")))
+    );
+}
+
+#[test]
+fn test_block_math() {
+    assert_eq!(
+        block_math(r"$$\text{$a$ and \$b}$$"),
+        Ok(("", Type::BlockMath.build(r"\text{$a$ and \$b}")))
+    );
+    assert!(block_math(r"$$a$b$$").is_err());
 }
 
-fn inline_math(input: &str) -> nom::IResult<&str, Part> {
-    delimited(cc::char('$'), bc::is_not("$"), cc::char('$'))(input)
-        .map_output(Type::InlineMath.builder())
+fn inline_math(input: &str) -> nom::IResult<&str, Part<'_>> {
+    alt((
+        delimited(cc::char('$'), math_body("$"), cc::char('$')),
+        delimited(bc::tag("\\("), math_body("\\)"), bc::tag("\\)")),
+    ))(input)
+    .map_output(Type::InlineMath.builder())
 }
 
-fn block_math(input: &str) -> nom::IResult<&str, Part> {
-    delimited(bc::tag("$$"), bc::is_not("$"), bc::tag("$$"))(input)
-        .map_output(Type::BlockMath.builder())
+fn block_math(input: &str) -> nom::IResult<&str, Part<'_>> {
+    alt((
+        delimited(bc::tag("$$"), math_body("$$"), bc::tag("$$")),
+        delimited(bc::tag("\\["), math_body("\\]"), bc::tag("\\]")),
+    ))(input)
+    .map_output(Type::BlockMath.builder())
 }
 
-fn environ(input: &str) -> nom::IResult<&str, Part> {
-    use nom::{combinator::*, multi::*};
-    recognize(move |input| {
-        let (input, name) = begin_environ(input)?;
-        many0_count(not(end_environ(name)).and(cc::anychar))
-            .and(end_environ(name))
-            .map(unit)
-            .parse(input)
+/// Recognizes a formula up to the closing delimiter `end`.
+///
+/// Backslash escapes like `\$` are skipped as a whole, and a bare `$` is only
+/// allowed within braces, as in `\text{if $x$ holds}`.
+fn math_body<'a>(end: &'static str) -> impl FnMut(&'a str) -> nom::IResult<&'a str, &'a str> {
+    use nom::{combinator::*, multi::*, sequence::preceded};
+    recognize(many1_count(preceded(
+        not(bc::tag(end)),
+        alt((
+            escaped_char,
+            comment_body,
+            brace_group,
+            cc::none_of("$").map(unit),
+        )),
+    )))
+}
+
+fn escaped_char(input: &str) -> nom::IResult<&str, ()> {
+    cc::char('\\')
+        .and(cc::anychar)
+        .map(unit)
+        .parse(input)
+}
+
+fn brace_group(input: &str) -> nom::IResult<&str, ()> {
+    use nom::multi::many0_count;
+    delimited(
+        cc::char('{'),
+        many0_count(alt((
+            escaped_char,
+            comment_body,
+            brace_group,
+            cc::none_of("}").map(unit),
+        ))),
+        cc::char('}'),
+    )
+    .map(unit)
+    .parse(input)
+}
+
+fn environ<'a>(input: &'a str, envs: &Environs) -> nom::IResult<&'a str, Part<'a>> {
+    use nom::combinator::*;
+    recognize(|input| {
+        let (mut input, name) = math_environ_name(input, "\\begin{", envs)?;
+        // environments of the same name may nest
+        let mut depth = 1usize;
+        loop {
+            if let Ok((rest, _)) = end_environ(name).parse(input) {
+                depth -= 1;
+                input = rest;
+                if depth == 0 {
+                    return Ok((input, ()));
+                }
+            } else if let Ok((rest, inner)) = environ_name(input, "\\begin{") {
+                if inner == name {
+                    depth += 1;
+                }
+                input = rest;
+            } else {
+                // skip escaped characters like `\\` and comments as a whole
+                (input, _) = alt((escaped_char, comment_body, cc::anychar.map(unit)))(input)?;
+            }
+        }
     })(input)
     .map_output(Type::Environ.builder())
 }
 
+#[test]
+fn test_environ() {
+    let mut envs = Environs::default();
+    for input in [
+        r"\begin{equation}\begin{aligned}a\end{aligned}\end{equation}",
+        r"\begin{matrix}\begin{matrix}a\end{matrix}\\\end{matrix}",
+        r"\begin{cases}a & b\\c & d\end{cases}",
+        r"\begin{smallmatrix}\\end{smallmatrix}\end{smallmatrix}",
+    ] {
+        assert_eq!(environ(input, &envs), Ok(("", Type::Environ.build(input))));
+    }
+    assert!(environ(r"\begin{matrix}\begin{matrix}\end{matrix}", &envs).is_err());
+    assert!(environ(r"\begin{foo}\end{foo}", &envs).is_err());
+    envs.insert("foo", super::EnvironKind::Math);
+    assert!(environ(r"\begin{foo}\end{foo}", &envs).is_ok());
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-struct EnvironName<'a>(&'a str);
+pub(super) struct EnvironName<'a>(pub &'a str);
 
 #[test]
 fn test_begin_environ() {
+    let envs = Environs::default();
     for name in "equation equation* 
     alignedat alignat alignat* aligned align align*
     gathered gather gather* CD multline darray
     dcases drcases matrix* pmatrix* 
-    bmatrix* Bmatrix* vmatrix* Vmatrix*"
+    bmatrix* Bmatrix* vmatrix* Vmatrix*
+    cases array split subarray smallmatrix pmatrix"
         .split_whitespace()
     {
         assert_eq!(
-            begin_environ(&format!("\\begin{{{}}}", name)),
+            math_environ_name(&format!("\\begin{{{}}}", name), "\\begin{", &envs),
             Ok(("", EnvironName(name)))
         );
     }
+    assert!(math_environ_name("\\begin{itemize}", "\\begin{", &envs).is_err());
 }
 
-fn begin_environ(input: &str) -> nom::IResult<&str, EnvironName> {
-    use bc::tag;
-    use nom::combinator::{opt, recognize};
-    let ast = |name: &'static str| recognize(tag(name).and(opt(tag("*"))));
-
-    // https://temml.org/docs/en/supported#environments
+/// Parses `\begin{name}` or `\end{name}`, depending on `cmd`.
+pub(super) fn environ_name<'a>(
+    input: &'a str,
+    cmd: &'static str,
+) -> nom::IResult<&'a str, EnvironName<'a>> {
     delimited(
-        tag("\\begin{").and(cc::space0),
-        alt((
-            ast("equation"),
-            tag("alignedat"),
-            ast("alignat"),
-            tag("aligned"),
-            ast("align"),
-            tag("gathered"),
-            ast("gather"),
-            tag("CD"),
-            tag("multline"),
-            alt((tag("darray"), tag("dcases"), tag("drcases"))),
-            recognize(opt(cc::one_of("pbBvV")).and(tag("matrix*"))),
-        )),
-        cc::space0.and(tag("}")),
+        bc::tag(cmd).and(cc::space0),
+        bc::take_while1(|c: char| c.is_alphanumeric() || c == '*'),
+        cc::space0.and(bc::tag("}")),
     )(input)
     .map_output(EnvironName)
 }
 
-fn end_environ(name: EnvironName) -> impl Parser<&str, (), nom::error::Error<&str>> {
+fn math_environ_name<'a>(
+    input: &'a str,
+    cmd: &'static str,
+    envs: &Environs,
+) -> nom::IResult<&'a str, EnvironName<'a>> {
+    nom::combinator::verify(
+        |i| environ_name(i, cmd),
+        |name: &EnvironName| envs.is_math(name.0),
+    )(input)
+}
+
+fn end_environ(name: EnvironName<'_>) -> impl Parser<&str, (), nom::error::Error<&str>> {
     delimited(
         bc::tag("\\end{").and(cc::space0),
         bc::tag(name.0),
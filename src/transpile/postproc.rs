@@ -8,6 +8,14 @@ enum Type {
     #[default]
     Text,
     Math,
+    /// The source of a formula that fails to render.
+    Failed,
+    /// A comment kept as a hidden HTML comment.
+    Comment,
+    /// HTML tags rendered from the text.
+    Markup,
+    /// The result of a Word field, with the field code in the decoration.
+    Field,
 }
 
 #[derive(Default)]
@@ -43,7 +51,7 @@ impl<'a> std::iter::IntoIterator for Piece<'a> {
 }
 
 impl<'a> Piece<'a> {
-    pub fn from_text(t: &'a str) -> Self {
+    pub fn from_text<T: Into<Cow<'a, str>>>(t: T) -> Self {
         Self {
             typ: Type::Text,
             content: t.into(),
@@ -57,6 +65,95 @@ impl<'a> Piece<'a> {
             ..Default::default()
         }
     }
+    pub fn from_markup<T: Into<Cow<'a, str>>>(m: T) -> Self {
+        Self {
+            typ: Type::Markup,
+            content: m.into(),
+            ..Default::default()
+        }
+    }
+    /// Builds a Word field, which shows `result` until Word updates it by
+    /// `code`, e.g. `SEQ Equation`.
+    pub fn from_field<T: Into<Cow<'a, str>>>(code: &str, result: T) -> Self {
+        Self {
+            typ: Type::Field,
+            content: result.into(),
+            dec: Decoration {
+                prefix: Some(
+                    format!(
+                        concat!(
+                            "<!--[if supportFields]><span style='mso-element:field-begin'></span> {} ",
+                            "<span style='mso-element:field-separator'></span><![endif]-->",
+                        ),
+                        html_escape::encode_text(code)
+                    )
+                    .into(),
+                ),
+                suffix: Some(
+                    "<!--[if supportFields]><span style='mso-element:field-end'></span><![endif]-->"
+                        .into(),
+                ),
+            },
+        }
+    }
+    pub fn from_failed(t: &'a str) -> Self {
+        Self {
+            typ: Type::Failed,
+            content: t.into(),
+            dec: Decoration {
+                prefix: Some(r#"<span style="color:red">"#.into()),
+                suffix: Some("</span>".into()),
+            },
+        }
+    }
+    pub fn from_comment(c: &'a str) -> Self {
+        let c = c.trim_start_matches('%').trim_end();
+        Self {
+            typ: Type::Comment,
+            // `-`, or `>` at the start, may end an HTML comment early
+            content: if c.starts_with('>') || c.contains('-') {
+                let c = c.replace('-', "&#45;");
+                match c.strip_prefix('>') {
+                    Some(rest) => format!("&gt;{}", rest).into(),
+                    None => c.into(),
+                }
+            } else {
+                c.into()
+            },
+            dec: Decoration {
+                prefix: Some("<!--".into()),
+                suffix: Some("-->".into()),
+            },
+        }
+    }
+    /// Removes the trailing whitespace of text.
+    pub fn trim_end(&mut self) {
+        if self.typ != Type::Text {
+            return;
+        }
+        match &mut self.content {
+            Cow::Borrowed(s) => *s = s.trim_end(),
+            Cow::Owned(s) => s.truncate(s.trim_end().len()),
+        }
+    }
+    pub fn is_math(&self) -> bool {
+        self.typ == Type::Math
+    }
+    pub fn into_content(self) -> Cow<'a, str> {
+        self.content
+    }
+}
+
+#[test]
+fn test_from_comment() {
+    let html = |c| {
+        Piece::from_comment(c)
+            .into_iter()
+            .collect::<String>()
+    };
+    assert_eq!(html("% a "), "<!-- a-->");
+    assert_eq!(html("% --->x"), "<!-- &#45;&#45;&#45;>x-->");
+    assert_eq!(html("%>"), "<!--&gt;-->");
 }
 
 pub(super) fn italic_math(enabled: bool) -> impl for<'a> FnMut(Piece<'a>) -> Piece<'a> {
@@ -73,7 +170,7 @@ pub(super) fn italic_math(enabled: bool) -> impl for<'a> FnMut(Piece<'a>) -> Pie
 
 pub(super) fn escape_html(p: Piece) -> Piece {
     match p.typ {
-        Type::Text => Piece {
+        Type::Text | Type::Failed | Type::Field => Piece {
             content: p.content.bind(html_escape::encode_safe),
             ..p
         },
@@ -81,18 +178,6 @@ pub(super) fn escape_html(p: Piece) -> Piece {
     }
 }
 
-pub(super) fn preserve_spaces(enabled: bool) -> impl for<'a> FnMut(Piece<'a>) -> Piece<'a> {
-    move |mut p| {
-        if enabled && p.typ == Type::Text {
-            p.dec = Decoration {
-                prefix: Some("<pre>".into()),
-                suffix: Some("</pre>".into()),
-            };
-        }
-        p
-    }
-}
-
 #[derive(Default)]
 struct Decoration {
     prefix: Option<StaticString>,
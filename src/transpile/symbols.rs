@@ -0,0 +1,172 @@
+use std::borrow::Cow;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Translates a text-mode symbol command, like `\ss` or `\&`.
+pub(super) fn symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        // escaped special characters
+        "\\&" => "&",
+        "\\%" => "%",
+        "\\$" => "$",
+        "\\#" => "#",
+        "\\_" => "_",
+        "\\{" | "\\textbraceleft" => "{",
+        "\\}" | "\\textbraceright" => "}",
+        "\\textbackslash" => "\\",
+        "\\textasciitilde" => "~",
+        "\\textasciicircum" => "^",
+        "\\textless" => "<",
+        "\\textgreater" => ">",
+        "\\textbar" => "|",
+        // spaces
+        "\\ " | "\\space" => " ",
+        "\\," | "\\thinspace" => "\u{2009}",
+        "\\enspace" => "\u{2002}",
+        "\\quad" => "\u{2003}",
+        "\\qquad" => "\u{2003}\u{2003}",
+        "\\nobreakspace" => "\u{a0}",
+        "\\-" => "\u{ad}",
+        "\\@" | "\\/" => "",
+        // punctuation
+        "\\ldots" | "\\dots" | "\\textellipsis" => "…",
+        "\\textendash" => "–",
+        "\\textemdash" => "—",
+        "\\textquoteleft" => "‘",
+        "\\textquoteright" => "’",
+        "\\textquotedblleft" => "“",
+        "\\textquotedblright" => "”",
+        "\\guillemotleft" | "\\guillemetleft" => "«",
+        "\\guillemotright" | "\\guillemetright" => "»",
+        "\\textexclamdown" => "¡",
+        "\\textquestiondown" => "¿",
+        "\\textbullet" => "•",
+        "\\textperiodcentered" => "·",
+        "\\S" | "\\textsection" => "§",
+        "\\P" | "\\textparagraph" => "¶",
+        "\\dag" | "\\textdagger" => "†",
+        "\\ddag" | "\\textdaggerdbl" => "‡",
+        "\\copyright" | "\\textcopyright" => "©",
+        "\\textregistered" => "®",
+        "\\texttrademark" => "™",
+        "\\pounds" | "\\textsterling" => "£",
+        "\\euro" | "\\texteuro" => "€",
+        "\\textyen" => "¥",
+        "\\textcent" => "¢",
+        "\\textdegree" => "°",
+        "\\textmu" => "µ",
+        "\\texttimes" => "×",
+        "\\textdiv" => "÷",
+        "\\textpm" => "±",
+        "\\textonehalf" => "½",
+        "\\textperthousand" => "‰",
+        "\\textnumero" => "№",
+        "\\TeX" => "TeX",
+        "\\LaTeX" => "LaTeX",
+        // letters
+        "\\ss" => "ß",
+        "\\SS" => "SS",
+        "\\ae" => "æ",
+        "\\AE" => "Æ",
+        "\\oe" => "œ",
+        "\\OE" => "Œ",
+        "\\o" => "ø",
+        "\\O" => "Ø",
+        "\\aa" => "å",
+        "\\AA" => "Å",
+        "\\l" => "ł",
+        "\\L" => "Ł",
+        "\\i" => "ı",
+        "\\j" => "ȷ",
+        "\\dh" => "ð",
+        "\\DH" => "Ð",
+        "\\th" => "þ",
+        "\\TH" => "Þ",
+        "\\ng" => "ŋ",
+        "\\NG" => "Ŋ",
+        _ => return None,
+    })
+}
+
+/// Returns the combining character of an accent command, like `\'` or `\c`.
+pub(super) fn accent(name: &str) -> Option<char> {
+    Some(match name {
+        "\\`" => '\u{300}',
+        "\\'" => '\u{301}',
+        "\\^" => '\u{302}',
+        "\\~" => '\u{303}',
+        "\\=" => '\u{304}',
+        "\\u" => '\u{306}',
+        "\\." => '\u{307}',
+        "\\\"" => '\u{308}',
+        "\\r" => '\u{30a}',
+        "\\H" => '\u{30b}',
+        "\\v" => '\u{30c}',
+        "\\d" => '\u{323}',
+        "\\c" => '\u{327}',
+        "\\k" => '\u{328}',
+        "\\b" => '\u{331}',
+        _ => return None,
+    })
+}
+
+/// Puts `accent` on the first character of `text`, and composes them if
+/// possible.
+pub(super) fn put_accent(text: &str, accent: char) -> String {
+    let mut chars = text.chars();
+    let base = match chars.next() {
+        // the dotless letters are only used to carry accents
+        Some('ı') => Some('i'),
+        Some('ȷ') => Some('j'),
+        c => c,
+    };
+    base.into_iter()
+        .chain(Some(accent))
+        .nfc()
+        .chain(chars)
+        .collect()
+}
+
+#[test]
+fn test_put_accent() {
+    assert_eq!(put_accent("e", '\u{301}'), "é");
+    assert_eq!(put_accent("ıx", '\u{308}'), "ïx");
+    assert_eq!(put_accent("c", '\u{327}'), "ç");
+    assert_eq!(put_accent("", '\u{303}'), "\u{303}");
+}
+
+/// Translates the ligatures and active characters in plain text, like `--`,
+/// ``` `` ``` and `~`.
+pub(super) fn ligatures(text: &str) -> Cow<'_, str> {
+    if !text.contains(['-', '`', '\'', '~']) {
+        return text.into();
+    }
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (translated, len) = match c {
+            '-' if rest.starts_with("---") => ("—", 3),
+            '-' if rest.starts_with("--") => ("–", 2),
+            '`' if rest.starts_with("``") => ("“", 2),
+            '`' => ("‘", 1),
+            '\'' if rest.starts_with("''") => ("”", 2),
+            '\'' => ("’", 1),
+            '~' => ("\u{a0}", 1),
+            _ => {
+                res.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+        res.push_str(translated);
+        rest = &rest[len..];
+    }
+    res.into()
+}
+
+#[test]
+fn test_ligatures() {
+    assert_eq!(ligatures("pages 1--2 --- ``don't''"), "pages 1–2 — “don’t”");
+    assert_eq!(ligatures("Fig.~1 `a'"), "Fig.\u{a0}1 ‘a’");
+    assert!(matches!(ligatures("a+b"), Cow::Borrowed(_)));
+}
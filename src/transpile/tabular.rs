@@ -0,0 +1,177 @@
+/// A column of a `tabular`, as given by its column specification.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(super) struct Column {
+    /// The value of CSS `text-align`.
+    pub align: &'static str,
+    /// The width of a paragraph column like `p{3cm}`, in CSS.
+    pub width: Option<String>,
+    /// Whether there are vertical rules on the left and on the right.
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Column {
+    pub fn new(align: &'static str) -> Column {
+        Column {
+            align,
+            width: None,
+            left: false,
+            right: false,
+        }
+    }
+}
+
+/// The most columns of a `tabular`, beyond which `*{n}{...}` and
+/// `\multicolumn` are cut short.
+pub(super) const MAX_COLUMNS: usize = 1000;
+
+/// Parses a column specification like `|l|c|p{3cm}|`.
+///
+/// Only the first of successive rules is counted, and the inter-column
+/// material like `@{}` is ignored.
+pub(super) fn parse_colspec(spec: &str) -> Vec<Column> {
+    let mut columns = vec![];
+    // a rule before the first column
+    let mut left = false;
+    let mut chars = spec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            'l' => columns.push(Column::new("left")),
+            'c' => columns.push(Column::new("center")),
+            'r' => columns.push(Column::new("right")),
+            // `X` of tabularx takes the remaining width
+            'X' => columns.push(Column::new("left")),
+            'p' | 'm' | 'b' => {
+                let width = group(&mut chars);
+                columns.push(Column {
+                    width: super::text::css_length(&width),
+                    ..Column::new("justify")
+                });
+            }
+            '|' => match columns.last_mut() {
+                Some(col) => col.right = true,
+                None => left = true,
+            },
+            '@' | '!' | '>' | '<' => {
+                group(&mut chars);
+            }
+            '*' => {
+                let n = group(&mut chars)
+                    .trim()
+                    .parse()
+                    .unwrap_or(0);
+                let repeated = parse_colspec(&group(&mut chars));
+                if repeated.is_empty() {
+                    continue;
+                }
+                let room = MAX_COLUMNS.saturating_sub(columns.len()) / repeated.len();
+                for _ in 0..n.min(room) {
+                    // a leading rule of the repeated columns follows the last column
+                    match columns.last_mut() {
+                        Some(col) if repeated[0].left => col.right = true,
+                        None if repeated[0].left => left = true,
+                        _ => (),
+                    }
+                    columns.extend_from_slice(&repeated);
+                    let first = columns.len() - repeated.len();
+                    columns[first].left = false;
+                }
+            }
+            _ => (),
+        }
+    }
+    if let Some(col) = columns.first_mut() {
+        col.left = left;
+    }
+    columns
+}
+
+/// Takes the content of the group that comes next from `chars`.
+fn group(chars: &mut std::str::Chars) -> String {
+    let mut res = String::new();
+    let mut depth = 0;
+    for c in chars.by_ref() {
+        match c {
+            '{' if depth == 0 => {
+                depth = 1;
+                continue;
+            }
+            '{' => depth += 1,
+            '}' if depth == 1 => break,
+            '}' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => continue,
+            // a single character as the argument
+            c if depth == 0 => {
+                res.push(c);
+                break;
+            }
+            _ => (),
+        }
+        res.push(c);
+    }
+    res
+}
+
+#[test]
+fn test_parse_colspec() {
+    let cols = parse_colspec("|l|c@{}r*{2}{|c}|p{3cm}");
+    assert_eq!(
+        cols.iter()
+            .map(|c| (c.align, c.left, c.right))
+            .collect::<Vec<_>>(),
+        vec![
+            ("left", true, true),
+            ("center", false, false),
+            ("right", false, true),
+            ("center", false, true),
+            ("center", false, true),
+            ("justify", false, false),
+        ]
+    );
+    assert_eq!(cols[5].width.as_deref(), Some("3cm"));
+    assert_eq!(
+        parse_colspec("*{99999999}{*{99999999}{c}}").len(),
+        MAX_COLUMNS
+    );
+}
+
+/// The borders of a cell.
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct Borders {
+    pub top: bool,
+    pub bottom: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+/// Returns the opening `<td>` of a cell.
+pub(super) fn cell_tag(
+    colspan: usize,
+    rowspan: usize,
+    column: &Column,
+    borders: Borders,
+) -> String {
+    // as `\tabcolsep` and `\arrayrulewidth` in LaTeX
+    let mut style = format!("padding:0 6pt;text-align:{}", column.align);
+    if let Some(width) = &column.width {
+        style += &format!(";width:{}", width);
+    }
+    for (side, enabled) in [
+        ("top", borders.top),
+        ("bottom", borders.bottom),
+        ("left", borders.left),
+        ("right", borders.right),
+    ] {
+        if enabled {
+            style += &format!(";border-{}:solid windowtext .5pt", side);
+        }
+    }
+    let mut tag = String::from("<td");
+    if colspan > 1 {
+        tag += &format!(r#" colspan="{}""#, colspan);
+    }
+    if rowspan > 1 {
+        tag += &format!(r#" rowspan="{}""#, rowspan);
+    }
+    tag + &format!(r#" style="{}">"#, style)
+}
@@ -0,0 +1,2152 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::bibliography::{Bibliography, Entry};
+use super::images::Images;
+use super::numbering::{self, Number, Numbering};
+use super::parser::{self, Part};
+use super::postproc::Piece;
+use super::symbols;
+use super::tabular;
+use super::theorems::{Theorem, Theorems};
+use super::{CitationStyle, Job};
+
+/// A font style that applies to a span of text.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Style {
+    Bold,
+    Italic,
+    /// Italic, or upright within italic text.
+    Emph,
+    Upright,
+    Medium,
+    Underline,
+    Code,
+    SmallCaps,
+    Sans,
+    Normal,
+}
+
+impl Style {
+    /// Commands taking the styled text as an argument, like `\textbf{...}`.
+    fn from_command(name: &str) -> Option<Style> {
+        Some(match name {
+            "\\textbf" => Style::Bold,
+            "\\textit" | "\\textsl" => Style::Italic,
+            "\\emph" => Style::Emph,
+            "\\textup" => Style::Upright,
+            "\\textmd" => Style::Medium,
+            "\\underline" => Style::Underline,
+            "\\texttt" => Style::Code,
+            "\\textsc" => Style::SmallCaps,
+            "\\textsf" => Style::Sans,
+            "\\textrm" | "\\textnormal" => Style::Normal,
+            _ => return None,
+        })
+    }
+
+    /// Declarations styling the rest of the group, like `{\bf ...}`.
+    fn from_declaration(name: &str) -> Option<Style> {
+        Some(match name {
+            "\\bf" | "\\bfseries" => Style::Bold,
+            "\\it" | "\\itshape" | "\\sl" | "\\slshape" => Style::Italic,
+            "\\em" => Style::Emph,
+            "\\upshape" => Style::Upright,
+            "\\mdseries" => Style::Medium,
+            "\\tt" | "\\ttfamily" => Style::Code,
+            "\\sc" | "\\scshape" => Style::SmallCaps,
+            "\\sf" | "\\sffamily" => Style::Sans,
+            "\\rm" | "\\rmfamily" | "\\normalfont" => Style::Normal,
+            _ => return None,
+        })
+    }
+
+    /// The opening and closing tags, given whether the text around is italic.
+    fn tags(self, italic: bool) -> (&'static str, &'static str) {
+        const SPAN_END: &str = "</span>";
+        match self {
+            Style::Bold => ("<b>", "</b>"),
+            Style::Italic => ("<i>", "</i>"),
+            Style::Emph if italic => (r#"<span style="font-style:normal">"#, SPAN_END),
+            Style::Emph => ("<i>", "</i>"),
+            Style::Upright => (r#"<span style="font-style:normal">"#, SPAN_END),
+            Style::Medium => (r#"<span style="font-weight:normal">"#, SPAN_END),
+            Style::Underline => ("<u>", "</u>"),
+            Style::Code => ("<code>", "</code>"),
+            Style::SmallCaps => (r#"<span style="font-variant:small-caps">"#, SPAN_END),
+            Style::Sans => (r#"<span style="font-family:sans-serif">"#, SPAN_END),
+            Style::Normal => (
+                r#"<span style="font-style:normal;font-weight:normal">"#,
+                SPAN_END,
+            ),
+        }
+    }
+
+    /// Whether the text inside is italic.
+    fn italic(self, italic: bool) -> bool {
+        match self {
+            Style::Italic => true,
+            Style::Emph => !italic,
+            Style::Upright | Style::Normal => false,
+            _ => italic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token<'a> {
+    /// A run of ordinary characters.
+    Text(&'a str),
+    /// A control sequence, including the backslash.
+    Command(&'a str),
+    Open,
+    Close,
+    /// An `&`, which separates the cells of a `tabular`.
+    Ampersand,
+    /// A part other than text, e.g. a formula.
+    Piece(usize),
+}
+
+/// Splits the text parts, and stands for the other parts by their indices.
+///
+/// The tokens are returned in reverse order, so that they can be popped.
+fn tokenize<'a>(parts: &[Part<'a>]) -> Vec<Token<'a>> {
+    let mut tokens = vec![];
+    for (i, p) in parts.iter().enumerate() {
+        if p.typ != parser::Type::Text {
+            tokens.push(Token::Piece(i));
+            continue;
+        }
+        let mut text = p.as_str();
+        while !text.is_empty() {
+            let end = text
+                .find(['\\', '{', '}', '&'])
+                .unwrap_or(text.len());
+            let (token, len) = match &text[..end] {
+                "" if text.starts_with('{') => (Token::Open, 1),
+                "" if text.starts_with('}') => (Token::Close, 1),
+                "" if text.starts_with('&') => (Token::Ampersand, 1),
+                "" => {
+                    let name = control_sequence(text);
+                    (Token::Command(name), name.len())
+                }
+                run => (Token::Text(run), run.len()),
+            };
+            tokens.push(token);
+            text = &text[len..];
+        }
+    }
+    tokens.reverse();
+    tokens
+}
+
+/// Returns the control sequence `text` starts with, i.e., a backslash followed
+/// by either letters or a single character.
+fn control_sequence(text: &str) -> &str {
+    let rest = &text[1..];
+    let len = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+        Some(0) => rest
+            .chars()
+            .next()
+            .map_or(0, char::len_utf8),
+        Some(n) => n,
+        None => rest.len(),
+    };
+    &text[..1 + len]
+}
+
+/// Whether `name` consists of letters, after which spaces are skipped.
+fn is_control_word(name: &str) -> bool {
+    name[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Node<'a> {
+    Text(Cow<'a, str>),
+    Piece(usize),
+    Group(Vec<Node<'a>>),
+    Styled(Style, Vec<Node<'a>>),
+    /// The end of a paragraph.
+    Par,
+    /// A line break within a paragraph.
+    Break,
+    /// The end of a paragraph, with some vertical space in CSS after it.
+    Skip(String),
+    /// A Word field with its code and the result shown before it is updated.
+    Field(String, String),
+    /// An `<img>` element.
+    Image(String),
+    /// A block element, e.g. a heading, with its tag and attributes.
+    Block(&'static str, &'static str, Vec<Node<'a>>),
+    /// An element containing paragraphs, with its opening and closing tags.
+    Container(Cow<'static, str>, Vec<Node<'a>>, &'static str),
+    /// The markup of a `tabular`, which is turned into cells.
+    Tabular(Mark<'a>),
+    /// An `\item` with its optional label, which starts an item of a list.
+    Item(Option<Vec<Node<'a>>>),
+    List(ListKind, Vec<Item<'a>>),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ListKind {
+    Itemize,
+    /// An `enumerate` at the given depth, starting from 0.
+    Enumerate(usize),
+    Description,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Mark<'a> {
+    /// `&`
+    Cell,
+    /// `\\`
+    Row,
+    /// `\hline`, or `\cline` over a range of columns.
+    Rule(Option<Range<usize>>),
+    /// `\multicolumn` with the number of columns, the column specification and
+    /// the content.
+    MultiColumn(usize, String, Vec<Node<'a>>),
+    /// `\multirow` with the number of rows and the content.
+    MultiRow(usize, Vec<Node<'a>>),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Item<'a> {
+    label: Option<Vec<Node<'a>>>,
+    body: Vec<Node<'a>>,
+}
+
+/// Where [`TreeBuilder::nodes`] stops.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Until {
+    Eof,
+    /// The `}` closing the current group, which is consumed.
+    Close,
+    /// The `]` closing an optional argument, which is consumed.
+    Bracket,
+    /// The `\end{...}` of the environment being built, which is consumed.
+    Environ,
+}
+
+struct TreeBuilder<'a> {
+    tokens: Vec<Token<'a>>,
+    /// Whether to recognize the commands of paragraphs and line breaks.
+    layout: bool,
+    number_sections: bool,
+    /// The numbers of the current section, subsection and subsubsection.
+    sections: [usize; 3],
+    title: Option<Vec<Node<'a>>>,
+    author: Option<Vec<Node<'a>>>,
+    date: Option<Vec<Node<'a>>>,
+    /// The number of `enumerate` environments around.
+    enumerate_depth: usize,
+    /// The number of `tabular` environments around.
+    tabular_depth: usize,
+    /// The environment being built, whose `\end` stops [`Until::Environ`].
+    environ: Option<&'a str>,
+    /// The name of the float around, like `Table`, which the caption is for,
+    /// and the separator after the number in the caption.
+    float: Option<(&'static str, &'static str)>,
+    /// The numbers of the last floats, by their names.
+    float_numbers: HashMap<&'static str, usize>,
+    numbering: &'a Numbering,
+    /// Whether to refer to equations by Word fields.
+    fields: bool,
+    theorems: Theorems,
+    bibliography: &'a Bibliography,
+    author_year: bool,
+    /// The keys cited so far, in the order of their numbers.
+    cited: Vec<&'a str>,
+    images: &'a Images,
+}
+
+impl<'a> TreeBuilder<'a> {
+    fn new(
+        tokens: Vec<Token<'a>>,
+        job: &Job,
+        numbering: &'a Numbering,
+        bibliography: &'a Bibliography,
+        images: &'a Images,
+    ) -> Self {
+        TreeBuilder {
+            tokens,
+            images,
+            numbering,
+            fields: job.equation_fields,
+            theorems: Theorems::default(),
+            bibliography,
+            author_year: job.citation_style == CitationStyle::AuthorYear,
+            cited: vec![],
+            layout: job.latex_layout,
+            number_sections: job.number_sections,
+            sections: [0; 3],
+            title: None,
+            author: None,
+            date: None,
+            enumerate_depth: 0,
+            tabular_depth: 0,
+            environ: None,
+            float: None,
+            float_numbers: HashMap::new(),
+        }
+    }
+
+    /// Builds the nodes up to `until`.
+    fn nodes(&mut self, until: Until) -> Vec<Node<'a>> {
+        let mut res = vec![];
+        while let Some(token) = self.tokens.pop() {
+            let node = match token {
+                Token::Text(t) if until == Until::Bracket && t.contains(']') => {
+                    let (t, rest) = t.split_once(']').unwrap();
+                    if !rest.is_empty() {
+                        self.tokens.push(Token::Text(rest));
+                    }
+                    push(&mut res, Node::Text(symbols::ligatures(t)));
+                    return res;
+                }
+                Token::Text(t) => Node::Text(symbols::ligatures(t)),
+                Token::Ampersand if self.tabular_depth > 0 => Node::Tabular(Mark::Cell),
+                Token::Ampersand => Node::Text("&".into()),
+                Token::Piece(i) => Node::Piece(i),
+                Token::Open => Node::Group(self.nodes(Until::Close)),
+                Token::Close if until == Until::Close => return res,
+                // leaves the brace to the enclosing group
+                Token::Close if until == Until::Bracket => {
+                    self.tokens.push(Token::Close);
+                    return res;
+                }
+                // an unmatched brace is kept as is
+                Token::Close => Node::Text("}".into()),
+                Token::Command("\\end")
+                    if until == Until::Environ && self.peek_group() == self.environ =>
+                {
+                    self.skip_spaces();
+                    self.argument();
+                    return res;
+                }
+                Token::Command(c) => {
+                    if let Some(style) = Style::from_declaration(c) {
+                        self.skip_spaces();
+                        res.push(Node::Styled(style, self.nodes(until)));
+                        return res;
+                    }
+                    match self.command(c) {
+                        Some(node) => node,
+                        None => {
+                            // unknown commands are kept as is, with the braces
+                            // of their arguments
+                            push(&mut res, Node::Text(c.into()));
+                            while self.tokens.last() == Some(&Token::Open) {
+                                self.tokens.pop();
+                                push(&mut res, Node::Text("{".into()));
+                                for node in self.nodes(Until::Close) {
+                                    push(&mut res, node);
+                                }
+                                push(&mut res, Node::Text("}".into()));
+                            }
+                            continue;
+                        }
+                    }
+                }
+            };
+            push(&mut res, node);
+        }
+        res
+    }
+
+    /// Builds the node of a known command.
+    fn command(&mut self, name: &'a str) -> Option<Node<'a>> {
+        if let Some(node) = self.tabular_command(name) {
+            return Some(node);
+        }
+        if let Some(node) = self.layout_command(name) {
+            return Some(node);
+        }
+        if let Some(node) = self.sectioning_command(name) {
+            return Some(node);
+        }
+        if let Some(node) = self.reference_command(name) {
+            return Some(node);
+        }
+        if let Some(node) = self.theorem_command(name) {
+            return Some(node);
+        }
+        if let Some(node) = self.citation_command(name) {
+            return Some(node);
+        }
+        if let Some(node) = self.float_command(name) {
+            return Some(node);
+        }
+        if let Some(node) = self.list_command(name) {
+            return Some(node);
+        }
+        if name == "\\begin" {
+            return self.environ();
+        }
+        let node = if let Some(style) = Style::from_command(name) {
+            Node::Styled(style, self.argument())
+        } else if let Some(s) = symbols::symbol(name) {
+            if is_control_word(name) {
+                self.skip_spaces();
+            }
+            Node::Text(s.into())
+        } else if let Some(accent) = symbols::accent(name) {
+            let mut arg = self.argument();
+            match arg.first_mut() {
+                Some(Node::Text(t)) => *t = symbols::put_accent(t, accent).into(),
+                _ => arg.insert(0, Node::Text(accent.to_string().into())),
+            }
+            Node::Group(arg)
+        } else {
+            return None;
+        };
+        Some(node)
+    }
+
+    /// Builds the headings of sections and the title.
+    fn sectioning_command(&mut self, name: &str) -> Option<Node<'a>> {
+        let (level, tag) = match name {
+            "\\section" => (0, "h1"),
+            "\\subsection" => (1, "h2"),
+            "\\subsubsection" => (2, "h3"),
+            "\\paragraph" | "\\subparagraph" => (3, "h4"),
+            "\\title" | "\\author" | "\\date" => {
+                let arg = Some(self.argument());
+                match name {
+                    "\\title" => self.title = arg,
+                    "\\author" => self.author = arg,
+                    _ => self.date = arg,
+                }
+                self.skip_spaces();
+                return Some(Node::Group(vec![]));
+            }
+            "\\maketitle" => {
+                self.skip_spaces();
+                let mut nodes = vec![];
+                // Word maps the classes to its Title and Subtitle styles
+                for (attrs, arg) in [
+                    (r#" class="MsoTitle""#, self.title.take()),
+                    (r#" class="MsoSubtitle""#, self.author.take()),
+                    (r#" class="MsoSubtitle""#, self.date.take()),
+                ] {
+                    nodes.extend(arg.map(|arg| Node::Block("p", attrs, arg)));
+                }
+                return Some(Node::Group(nodes));
+            }
+            "\\and" => return Some(Node::Text(" and ".into())),
+            "\\thanks" => {
+                self.argument();
+                return Some(Node::Group(vec![]));
+            }
+            _ => return None,
+        };
+        let starred = self.optional_text("*");
+        // the short title for the table of contents
+        self.optional_argument();
+        let mut nodes = self.argument();
+        self.skip_spaces();
+        // paragraphs are not numbered, as in the default `secnumdepth`
+        if !starred && level < self.sections.len() {
+            self.sections[level] += 1;
+            self.sections[level + 1..].fill(0);
+            if self.number_sections {
+                let number = self.sections[..=level]
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<_>>()
+                    .join(".");
+                nodes.insert(0, Node::Text(format!("{} ", number).into()));
+            }
+        }
+        Some(Node::Block(tag, "", nodes))
+    }
+
+    /// Builds the references to equations, and drops the labels in text.
+    fn reference_command(&mut self, name: &str) -> Option<Node<'a>> {
+        let node = match name {
+            "\\label" => {
+                self.raw_argument();
+                Node::Group(vec![])
+            }
+            "\\ref" | "\\eqref" => {
+                let label = self.raw_argument();
+                let label = label.trim();
+                let number = match self.numbering.labels.get(label) {
+                    Some(Number::Auto(n)) if self.fields => {
+                        let code = format!("REF {} \\h", numbering::bookmark(label));
+                        Node::Field(code, n.to_string())
+                    }
+                    _ => Node::Text(self.numbering.referred(label).into()),
+                };
+                match name {
+                    "\\ref" => number,
+                    _ => Node::Group(vec![Node::Text("(".into()), number, Node::Text(")".into())]),
+                }
+            }
+            _ => return None,
+        };
+        Some(node)
+    }
+
+    /// Builds the argument of a command, which is either a group or a single
+    /// token.
+    fn argument(&mut self) -> Vec<Node<'a>> {
+        self.skip_spaces();
+        match self.tokens.pop() {
+            Some(Token::Open) => self.nodes(Until::Close),
+            Some(Token::Text(t)) => {
+                let len = t
+                    .chars()
+                    .next()
+                    .map_or(0, char::len_utf8);
+                if len < t.len() {
+                    self.tokens.push(Token::Text(&t[len..]));
+                }
+                vec![Node::Text(symbols::ligatures(&t[..len]))]
+            }
+            Some(Token::Piece(i)) => vec![Node::Piece(i)],
+            Some(Token::Command(c)) => {
+                vec![Node::Text(symbols::symbol(c).unwrap_or(c).into())]
+            }
+            Some(token @ (Token::Close | Token::Ampersand)) => {
+                self.tokens.push(token);
+                vec![]
+            }
+            None => vec![],
+        }
+    }
+
+    /// Builds the node of a command about paragraphs and line breaks, if
+    /// they are laid out.
+    fn layout_command(&mut self, name: &str) -> Option<Node<'a>> {
+        if !self.layout {
+            return None;
+        }
+        let node = match name {
+            "\\par" => Node::Par,
+            "\\\\" | "\\newline" | "\\linebreak" => {
+                // `\\*` and `\\[1em]`
+                self.optional_text("*");
+                self.optional_argument();
+                Node::Break
+            }
+            "\\vspace" | "\\vskip" => {
+                self.optional_text("*");
+                let arg = self.argument();
+                let len = arg
+                    .iter()
+                    .filter_map(|n| match n {
+                        Node::Text(t) => Some(t.as_ref()),
+                        _ => None,
+                    })
+                    .collect::<String>();
+                match css_length(&len) {
+                    Some(skip) => Node::Skip(skip),
+                    None => Node::Par,
+                }
+            }
+            "\\smallskip" => Node::Skip("3pt".into()),
+            "\\medskip" => Node::Skip("6pt".into()),
+            "\\bigskip" => Node::Skip("12pt".into()),
+            // paragraphs are not indented anyway
+            "\\noindent" | "\\indent" => Node::Group(vec![]),
+            _ => return None,
+        };
+        self.skip_spaces();
+        Some(node)
+    }
+
+    /// Builds a text environment after `\begin`, if it is known.
+    fn environ(&mut self) -> Option<Node<'a>> {
+        self.skip_spaces();
+        let name = self.peek_group()?;
+        let outer = self.environ.replace(name);
+        let node = self.known_environ(name);
+        self.environ = outer;
+        node
+    }
+
+    fn known_environ(&mut self, name: &'a str) -> Option<Node<'a>> {
+        let kind = match name {
+            "itemize" => ListKind::Itemize,
+            "enumerate" => ListKind::Enumerate(self.enumerate_depth),
+            "description" => ListKind::Description,
+            name @ ("tabular" | "tabular*" | "tabularx") => {
+                self.argument();
+                if name != "tabular" {
+                    // the width
+                    self.argument();
+                }
+                return Some(self.tabular());
+            }
+            "table" | "table*" => {
+                self.argument();
+                return Some(self.float("Table", ":"));
+            }
+            "figure" | "figure*" => {
+                self.argument();
+                return Some(self.float("Figure", "."));
+            }
+            "proof" => {
+                self.argument();
+                return Some(self.proof());
+            }
+            "center" | "flushleft" | "flushright" | "quote" | "quotation" | "verse"
+            | "abstract" => {
+                self.argument();
+                return Some(self.block_environ(name));
+            }
+            name => {
+                let theorem = self.theorems.get(name)?;
+                self.argument();
+                return Some(self.theorem(theorem));
+            }
+        };
+        self.argument();
+        Some(self.list(kind))
+    }
+
+    /// Builds an environment of paragraphs like `center` or `quote`.
+    fn block_environ(&mut self, name: &str) -> Node<'a> {
+        let mut nodes = self.nodes(Until::Environ);
+        trim(&mut nodes);
+        self.skip_spaces();
+        let (open, close) = match name {
+            "center" => (r#"<div style="text-align:center">"#, "</div>"),
+            "flushleft" => (r#"<div style="text-align:left">"#, "</div>"),
+            "flushright" => (r#"<div style="text-align:right">"#, "</div>"),
+            _ => ("<blockquote>", "</blockquote>"),
+        };
+        if name == "abstract" {
+            let heading = Node::Styled(Style::Bold, vec![Node::Text("Abstract".into())]);
+            nodes.insert(
+                0,
+                Node::Block("p", r#" style="text-align:center""#, vec![heading]),
+            );
+        }
+        Node::Container(open.into(), nodes, close)
+    }
+
+    fn float(&mut self, name: &'static str, sep: &'static str) -> Node<'a> {
+        // the placement
+        self.optional_argument();
+        let float = self.float.replace((name, sep));
+        let mut nodes = self.nodes(Until::Environ);
+        self.float = float;
+        trim(&mut nodes);
+        self.skip_spaces();
+        Node::Group(nodes)
+    }
+
+    /// Builds the captions and images of floats, and drops their alignment.
+    fn float_command(&mut self, name: &str) -> Option<Node<'a>> {
+        let node = match name {
+            "\\caption" => {
+                self.optional_argument();
+                let mut nodes = self.argument();
+                if let Some((name, sep)) = self.float {
+                    let n = self
+                        .float_numbers
+                        .entry(name)
+                        .or_default();
+                    *n += 1;
+                    nodes.insert(0, Node::Text(format!("{} {}{} ", name, n, sep).into()));
+                }
+                self.skip_spaces();
+                Node::Block("p", r#" class="MsoCaption""#, nodes)
+            }
+            "\\includegraphics" => {
+                self.optional_text("*");
+                let options = self
+                    .optional_raw_argument()
+                    .unwrap_or_default();
+                let path = self.raw_argument();
+                match self.images.get(path.trim()) {
+                    Some(image) => Node::Image(image.tag(&options)),
+                    // which fails to read, in tolerant mode
+                    None => Node::Text(format!("[{}]", path.trim()).into()),
+                }
+            }
+            "\\centering" | "\\raggedright" | "\\raggedleft" => {
+                self.skip_spaces();
+                Node::Group(vec![])
+            }
+            _ => return None,
+        };
+        Some(node)
+    }
+
+    /// Builds citations, and the list of references at `\bibliography`.
+    fn citation_command(&mut self, name: &str) -> Option<Node<'a>> {
+        match name {
+            "\\cite" | "\\citep" | "\\parencite" | "\\citet" | "\\textcite" | "\\citeauthor"
+            | "\\citeyear" | "\\nocite" => (),
+            "\\bibliography" | "\\printbibliography" => {
+                match name {
+                    "\\bibliography" => self.raw_argument(),
+                    // the options of biblatex
+                    _ => self
+                        .optional_raw_argument()
+                        .unwrap_or_default(),
+                };
+                self.skip_spaces();
+                return Some(self.references());
+            }
+            "\\bibliographystyle" => {
+                self.raw_argument();
+                self.skip_spaces();
+                return Some(Node::Group(vec![]));
+            }
+            _ => return None,
+        }
+        // the full author list of `\citet*`, which is not distinguished
+        self.optional_text("*");
+        let first = self.optional_argument();
+        let (pre, mut post) = match self.optional_argument() {
+            Some(post) => (first.unwrap_or_default(), post),
+            None => (vec![], first.unwrap_or_default()),
+        };
+        let keys = self.raw_argument();
+        let keys = keys
+            .split(',')
+            .map(str::trim)
+            .filter(|k| !k.is_empty());
+        if name == "\\nocite" {
+            for key in keys {
+                match key {
+                    "*" => {
+                        for entry in self.bibliography.entries() {
+                            self.cite(&entry.key);
+                        }
+                    }
+                    key => {
+                        self.cite(key);
+                    }
+                }
+            }
+            return Some(Node::Group(vec![]));
+        }
+        let (open, close) = match self.author_year {
+            true => ("(", ")"),
+            false => ("[", "]"),
+        };
+        let (sep, textual) = match name {
+            "\\citet" | "\\textcite" => ("; ", true),
+            "\\citeauthor" | "\\citeyear" => (", ", false),
+            _ if self.author_year => ("; ", false),
+            _ => (", ", false),
+        };
+        let mut items = vec![];
+        for key in keys {
+            let (number, entry) = self.cite(key);
+            let authors = entry.map_or("?".into(), Entry::short_authors);
+            let year = entry.map_or("?", |e| e.year.as_str());
+            items.push(match name {
+                "\\citeauthor" => authors,
+                "\\citeyear" => year.into(),
+                _ if textual && self.author_year => format!("{} ({}", authors, year),
+                _ if textual => format!("{} [{}", authors, number),
+                _ if self.author_year => format!("{}, {}", authors, year),
+                _ => number,
+            });
+        }
+        let mut nodes = vec![];
+        if !textual && name != "\\citeauthor" && name != "\\citeyear" {
+            nodes.push(Node::Text(open.into()));
+            if !pre.is_empty() {
+                nodes.extend(pre);
+                nodes.push(Node::Text(" ".into()));
+            }
+        }
+        let n = items.len();
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                nodes.push(Node::Text(sep.into()));
+            }
+            nodes.push(Node::Text(item.into()));
+            // the textual citations are closed one by one, the last after the
+            // note
+            if textual && i + 1 < n {
+                nodes.push(Node::Text(close.into()));
+            }
+        }
+        if !post.is_empty() {
+            nodes.push(Node::Text(", ".into()));
+            nodes.append(&mut post);
+        }
+        if name != "\\citeauthor" && name != "\\citeyear" {
+            nodes.push(Node::Text(close.into()));
+        }
+        Some(Node::Group(nodes))
+    }
+
+    /// Cites `key`, and returns its number as a string with its entry, or `?`
+    /// if it is not in the bibliography.
+    fn cite(&mut self, key: &str) -> (String, Option<&'a Entry>) {
+        let Some(entry) = self.bibliography.get(key) else {
+            return ("?".into(), None);
+        };
+        let n = match self
+            .cited
+            .iter()
+            .position(|k| *k == key)
+        {
+            Some(i) => i + 1,
+            None => {
+                self.cited.push(&entry.key);
+                self.cited.len()
+            }
+        };
+        (n.to_string(), Some(entry))
+    }
+
+    /// Builds the list of the cited references, in the order of their numbers
+    /// or sorted by authors.
+    fn references(&mut self) -> Node<'a> {
+        let bibliography = self.bibliography;
+        let mut entries = self
+            .cited
+            .iter()
+            .filter_map(|key| bibliography.get(key))
+            .collect::<Vec<_>>();
+        if self.author_year {
+            entries.sort_by_cached_key(|e| (e.short_authors(), e.year.clone()));
+        }
+        let mut nodes = vec![Node::Block("h1", "", vec![Node::Text("References".into())])];
+        for (i, entry) in entries.into_iter().enumerate() {
+            let mut item = vec![];
+            if !self.author_year {
+                item.push(Node::Text(format!("[{}] ", i + 1).into()));
+            }
+            item.append(&mut reference(entry, self.author_year));
+            // a hanging indent
+            nodes.push(Node::Block(
+                "p",
+                r#" style="margin-left:2em;text-indent:-2em""#,
+                item,
+            ));
+        }
+        Node::Group(nodes)
+    }
+
+    /// Declares theorem-like environments.
+    fn theorem_command(&mut self, name: &str) -> Option<Node<'a>> {
+        match name {
+            "\\newtheorem" => {
+                let numbered = !self.optional_text("*");
+                let env = self.raw_argument();
+                let shared = self.optional_raw_argument();
+                let title = self.raw_argument();
+                let within = self.optional_raw_argument();
+                let (shared, within) = (shared.as_deref(), within.as_deref());
+                self.theorems
+                    .define(env.trim(), numbered, shared, &title, within);
+            }
+            "\\theoremstyle" => {
+                let style = self.raw_argument();
+                self.theorems.set_style(&style);
+            }
+            _ => return None,
+        }
+        self.skip_spaces();
+        Some(Node::Group(vec![]))
+    }
+
+    /// Builds a theorem-like environment, with a heading like
+    /// `Theorem 2 (Fermat).`
+    fn theorem(&mut self, theorem: Theorem) -> Node<'a> {
+        let note = self.optional_argument();
+        let number = self
+            .theorems
+            .step(&theorem, &self.sections);
+        let mut heading = vec![Node::Text(
+            match number {
+                Some(number) => format!("{} {}", theorem.title, number),
+                None => theorem.title,
+            }
+            .into(),
+        )];
+        if let Some(mut note) = note {
+            note.insert(0, Node::Text(" (".into()));
+            note.push(Node::Text(")".into()));
+            heading.push(Node::Styled(Style::Medium, note));
+        }
+        heading.push(Node::Text(".".into()));
+        let body = self.theorem_body();
+        let style = match theorem.italic {
+            true => Style::Italic,
+            false => Style::Upright,
+        };
+        theorem_block(
+            Node::Styled(Style::Bold, heading),
+            Node::Styled(style, body),
+            self.layout,
+        )
+    }
+
+    /// Builds a `proof`, which ends with a tombstone.
+    fn proof(&mut self) -> Node<'a> {
+        let mut heading = self
+            .optional_argument()
+            .unwrap_or_else(|| vec![Node::Text("Proof".into())]);
+        heading.push(Node::Text(".".into()));
+        let mut body = self.theorem_body();
+        push(&mut body, Node::Text(" ∎".into()));
+        theorem_block(
+            Node::Styled(Style::Italic, heading),
+            Node::Styled(Style::Upright, body),
+            self.layout,
+        )
+    }
+
+    fn theorem_body(&mut self) -> Vec<Node<'a>> {
+        let mut body = self.nodes(Until::Environ);
+        trim(&mut body);
+        body
+    }
+
+    /// Builds the nodes of the commands only known in a `tabular`.
+    fn tabular_command(&mut self, name: &str) -> Option<Node<'a>> {
+        if self.tabular_depth == 0 {
+            return None;
+        }
+        let mark = match name {
+            "\\\\" | "\\tabularnewline" => {
+                self.optional_text("*");
+                self.optional_argument();
+                Mark::Row
+            }
+            "\\hline" | "\\toprule" | "\\midrule" | "\\bottomrule" => Mark::Rule(None),
+            "\\cline" | "\\cmidrule" => {
+                // the trimming of `\cmidrule`
+                if self.optional_text("(") {
+                    if let Some(Token::Text(t)) = self.tokens.last_mut() {
+                        *t = t
+                            .split_once(')')
+                            .map_or("", |(_, rest)| rest);
+                    }
+                }
+                let range = self.raw_argument();
+                let (a, b) = range.split_once('-')?;
+                let (a, b) = (a.trim().parse::<usize>().ok()?, b.trim().parse().ok()?);
+                Mark::Rule(Some(a.max(1) - 1..b))
+            }
+            "\\multicolumn" => {
+                let n: usize = self
+                    .raw_argument()
+                    .trim()
+                    .parse()
+                    .unwrap_or(1);
+                let spec = self.raw_argument();
+                Mark::MultiColumn(n.min(tabular::MAX_COLUMNS), spec, self.argument())
+            }
+            "\\multirow" => {
+                // the vertical position
+                self.optional_argument();
+                let n: isize = self
+                    .raw_argument()
+                    .trim()
+                    .parse()
+                    .unwrap_or(1);
+                // the width, and the vertical fixup
+                self.raw_argument();
+                self.optional_argument();
+                Mark::MultiRow(n.max(1) as usize, self.argument())
+            }
+            _ => return None,
+        };
+        self.skip_spaces();
+        Some(Node::Tabular(mark))
+    }
+
+    /// Builds a `tabular` as an HTML table, where `\hline` and vertical rules
+    /// become borders.
+    fn tabular(&mut self) -> Node<'a> {
+        // the vertical position
+        self.optional_argument();
+        let columns = tabular::parse_colspec(&self.raw_argument());
+        self.tabular_depth += 1;
+        let nodes = self.nodes(Until::Environ);
+        self.tabular_depth -= 1;
+        self.skip_spaces();
+
+        // the rows with the rules above them
+        let mut rows = vec![(vec![], vec![vec![]])];
+        for node in end_declarations(nodes) {
+            let (rules, cells) = rows.last_mut().unwrap();
+            match node {
+                Node::Tabular(Mark::Cell) => cells.push(vec![]),
+                Node::Tabular(Mark::Row) => rows.push((vec![], vec![vec![]])),
+                Node::Tabular(Mark::Rule(range)) => rules.push(range),
+                node => push(cells.last_mut().unwrap(), node),
+            }
+        }
+        // the rules after the last `\\`, unless a row follows them
+        let (mut bottom_rules, last) = rows.pop().unwrap();
+        if !last.iter().flatten().all(is_blank) {
+            rows.push((bottom_rules, last));
+            bottom_rules = vec![];
+        }
+
+        let ruled = |rules: &[Option<Range<usize>>], span: &Range<usize>| {
+            rules.iter().any(|r| match r {
+                Some(r) => r.start < span.end && span.start < r.end,
+                None => true,
+            })
+        };
+        let n = rows.len();
+        // the numbers of rows below still taken by `\multirow`, by columns
+        let mut covered = vec![0; columns.len()];
+        let mut table = vec![];
+        for (i, (rules, cells)) in rows.into_iter().enumerate() {
+            let mut tr = vec![];
+            let mut col = 0;
+            for mut nodes in cells {
+                // the cells under `\multirow` are left empty
+                if covered.get(col).is_some_and(|&n| n > 0) {
+                    col += 1;
+                    continue;
+                }
+                let (colspan, spec, rowspan) = spans(&mut nodes);
+                let span = col..col + colspan;
+                let column = match spec {
+                    Some(spec) => tabular::parse_colspec(&spec)
+                        .into_iter()
+                        .next(),
+                    None => columns
+                        .get(col)
+                        .map(|c| tabular::Column {
+                            right: columns
+                                .get(span.end - 1)
+                                .is_some_and(|c| c.right),
+                            ..c.clone()
+                        }),
+                }
+                .unwrap_or_else(|| tabular::Column::new("left"));
+                let borders = tabular::Borders {
+                    top: ruled(&rules, &span),
+                    bottom: i + 1 == n && ruled(&bottom_rules, &span),
+                    left: column.left,
+                    right: column.right,
+                };
+                if covered.len() < span.end {
+                    covered.resize(span.end, 0);
+                }
+                covered[span.clone()].fill(rowspan);
+                trim(&mut nodes);
+                let tag = tabular::cell_tag(colspan, rowspan, &column, borders);
+                tr.push(Node::Container(tag.into(), nodes, "</td>"));
+                col = span.end;
+            }
+            for n in covered.iter_mut() {
+                *n = n.saturating_sub(1);
+            }
+            table.push(Node::Container("<tr>".into(), tr, "</tr>"));
+        }
+        Node::Container(
+            r#"<table style="border-collapse:collapse">"#.into(),
+            table,
+            "</table>",
+        )
+    }
+
+    /// Takes the optional argument that comes next as TeX source, if any.
+    fn optional_raw_argument(&mut self) -> Option<String> {
+        self.skip_spaces();
+        if !self.optional_text("[") {
+            return None;
+        }
+        let mut res = String::new();
+        while let Some(token) = self.tokens.pop() {
+            match token {
+                Token::Text(t) => match t.split_once(']') {
+                    Some((t, rest)) => {
+                        res.push_str(t);
+                        if !rest.is_empty() {
+                            self.tokens.push(Token::Text(rest));
+                        }
+                        break;
+                    }
+                    None => res.push_str(t),
+                },
+                Token::Command(t) => res.push_str(t),
+                Token::Open => res.push('{'),
+                Token::Close => res.push('}'),
+                Token::Ampersand => res.push('&'),
+                Token::Piece(_) => (),
+            }
+        }
+        Some(res)
+    }
+
+    /// Takes the argument that comes next as TeX source.
+    fn raw_argument(&mut self) -> String {
+        self.skip_spaces();
+        let mut res = String::new();
+        let mut depth = 0;
+        while let Some(token) = self.tokens.pop() {
+            match token {
+                Token::Open => {
+                    depth += 1;
+                    if depth == 1 {
+                        continue;
+                    }
+                    res.push('{');
+                }
+                // no argument before the end of the group or cell
+                token @ (Token::Close | Token::Ampersand) if depth == 0 => {
+                    self.tokens.push(token);
+                    break;
+                }
+                Token::Close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                    res.push('}');
+                }
+                Token::Text(t) if depth == 0 => {
+                    // a single character as the argument
+                    let len = t
+                        .chars()
+                        .next()
+                        .map_or(0, char::len_utf8);
+                    if len < t.len() {
+                        self.tokens.push(Token::Text(&t[len..]));
+                    }
+                    res.push_str(&t[..len]);
+                }
+                Token::Text(t) | Token::Command(t) => res.push_str(t),
+                Token::Ampersand => res.push('&'),
+                // formulas are not expected
+                Token::Piece(_) => (),
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+        res
+    }
+
+    /// Builds the marks of items, which are put into lists by [`Self::list`].
+    fn list_command(&mut self, name: &str) -> Option<Node<'a>> {
+        if name != "\\item" {
+            return None;
+        }
+        let label = self.optional_argument();
+        self.skip_spaces();
+        Some(Node::Item(label))
+    }
+
+    fn list(&mut self, kind: ListKind) -> Node<'a> {
+        // the options of the `enumitem` package
+        self.optional_argument();
+        let depth = self.enumerate_depth;
+        if let ListKind::Enumerate(d) = kind {
+            self.enumerate_depth = d + 1;
+        }
+        let nodes = self.nodes(Until::Environ);
+        self.enumerate_depth = depth;
+        self.skip_spaces();
+        let mut items = vec![];
+        for node in nodes {
+            match (node, items.last_mut()) {
+                (Node::Item(label), _) => items.push(Item {
+                    label,
+                    body: vec![],
+                }),
+                (node, Some(Item { body, .. })) => body.push(node),
+                // spaces before the first item
+                (Node::Text(t), None) if t.trim().is_empty() => (),
+                (node, None) => items.push(Item {
+                    label: None,
+                    body: vec![node],
+                }),
+            }
+        }
+        Node::List(kind, items)
+    }
+
+    /// Returns the content of the group that comes next, if it is plain text.
+    fn peek_group(&self) -> Option<&'a str> {
+        match self.tokens.as_slice() {
+            [.., Token::Close, Token::Text(t), Token::Open] => Some(t.trim()),
+            _ => None,
+        }
+    }
+
+    /// Skips `text` if it comes next, and returns whether it does.
+    fn optional_text(&mut self, text: &str) -> bool {
+        if let Some(Token::Text(t)) = self.tokens.last_mut() {
+            if let Some(rest) = t.strip_prefix(text) {
+                *t = rest;
+                if t.is_empty() {
+                    self.tokens.pop();
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Builds an optional argument like `[1em]`, if any.
+    fn optional_argument(&mut self) -> Option<Vec<Node<'a>>> {
+        self.skip_spaces();
+        self.optional_text("[")
+            .then(|| self.nodes(Until::Bracket))
+    }
+
+    /// Skips the spaces after a control word, as TeX does.
+    fn skip_spaces(&mut self) {
+        if let Some(Token::Text(t)) = self.tokens.last_mut() {
+            *t = t.trim_start();
+            if t.is_empty() {
+                self.tokens.pop();
+            }
+        }
+    }
+}
+
+/// Formats an entry of the references, like `D. E. Knuth. The TeXbook.
+/// Addison-Wesley, 1984.`
+fn reference<'a>(entry: &Entry, author_year: bool) -> Vec<Node<'a>> {
+    let mut authors = entry.full_authors();
+    if author_year && !entry.year.is_empty() {
+        authors += &format!(" ({})", entry.year);
+    }
+    let mut head = vec![];
+    for mut s in [authors, entry.title.clone()] {
+        if !s.is_empty() {
+            if !s.ends_with(['.', '?', '!']) {
+                s.push('.');
+            }
+            head.push(s);
+        }
+    }
+    let mut nodes = vec![Node::Text(head.join(" ").into())];
+
+    let mut details = vec![];
+    let mut volume = entry.volume.clone();
+    if !entry.issue.is_empty() {
+        volume += &format!("({})", entry.issue);
+    }
+    details.push(volume);
+    details.push(entry.pages.clone());
+    details.push(entry.publisher.clone());
+    if !author_year {
+        details.push(entry.year.clone());
+    }
+    details.retain(|s| !s.is_empty());
+    if entry.container.is_empty() && details.is_empty() {
+        return nodes;
+    }
+    nodes.push(Node::Text(" ".into()));
+    if !entry.container.is_empty() {
+        let container = Node::Text(entry.container.clone().into());
+        nodes.push(Node::Styled(Style::Italic, vec![container]));
+        if !details.is_empty() {
+            nodes.push(Node::Text(", ".into()));
+        }
+    }
+    nodes.push(Node::Text(format!("{}.", details.join(", ")).into()));
+    nodes
+}
+
+/// Puts the heading of a theorem-like environment at the start of its first
+/// paragraph, or of a paragraph of its own without the LaTeX layout.
+fn theorem_block<'a>(heading: Node<'a>, body: Node<'a>, layout: bool) -> Node<'a> {
+    if !layout {
+        return Node::Block("p", "", vec![heading, Node::Text(" ".into()), body]);
+    }
+    Node::Group(vec![
+        Node::Par,
+        heading,
+        Node::Text(" ".into()),
+        body,
+        Node::Par,
+    ])
+}
+
+/// Ends the declarations like `\bfseries` at the end of the cells, as each
+/// cell of a `tabular` is a group.
+fn end_declarations(nodes: Vec<Node>) -> Vec<Node> {
+    let mut res = vec![];
+    for node in nodes {
+        match node {
+            Node::Styled(style, mut nodes) => {
+                match nodes
+                    .iter()
+                    .position(|n| matches!(n, Node::Tabular(_)))
+                {
+                    Some(i) => {
+                        let rest = nodes.split_off(i);
+                        res.push(Node::Styled(style, nodes));
+                        res.append(&mut end_declarations(rest));
+                    }
+                    None => res.push(Node::Styled(style, nodes)),
+                }
+            }
+            node => res.push(node),
+        }
+    }
+    res
+}
+
+/// Takes `\multicolumn` and `\multirow` out of a cell, and returns the numbers
+/// of columns and rows it spans, with its own column specification.
+fn spans(nodes: &mut [Node]) -> (usize, Option<String>, usize) {
+    for node in nodes.iter_mut() {
+        if let Node::Tabular(Mark::MultiColumn(..) | Mark::MultiRow(..)) = node {
+            let (colspan, spec, rowspan, mut content) = match std::mem::replace(node, Node::Par) {
+                Node::Tabular(Mark::MultiColumn(n, spec, content)) => (n, Some(spec), 1, content),
+                Node::Tabular(Mark::MultiRow(n, content)) => (1, None, n, content),
+                _ => unreachable!(),
+            };
+            // one may be nested in the other
+            let (c, s, r) = spans(&mut content);
+            *node = Node::Group(content);
+            return (colspan.max(c), spec.or(s), rowspan.max(r));
+        }
+    }
+    (1, None, 1)
+}
+
+fn is_blank(node: &Node) -> bool {
+    matches!(node, Node::Text(t) if t.trim().is_empty())
+}
+
+/// Trims the spaces around the content of a cell or an environment.
+fn trim(nodes: &mut Vec<Node>) {
+    trim_start(nodes);
+    trim_end(nodes);
+}
+
+fn trim_start(nodes: &mut Vec<Node>) {
+    match nodes.first_mut() {
+        Some(Node::Text(Cow::Borrowed(s))) => *s = s.trim_start(),
+        Some(Node::Text(Cow::Owned(s))) => *s = s.trim_start().to_owned(),
+        Some(Node::Group(nodes) | Node::Styled(_, nodes)) => trim_start(nodes),
+        _ => return,
+    }
+    if matches!(nodes.first(), Some(Node::Text(t)) if t.is_empty()) {
+        nodes.remove(0);
+    }
+}
+
+fn trim_end(nodes: &mut Vec<Node>) {
+    match nodes.last_mut() {
+        Some(Node::Text(Cow::Borrowed(s))) => *s = s.trim_end(),
+        Some(Node::Text(Cow::Owned(s))) => s.truncate(s.trim_end().len()),
+        Some(Node::Group(nodes) | Node::Styled(_, nodes)) => trim_end(nodes),
+        _ => return,
+    }
+    if matches!(nodes.last(), Some(Node::Text(t)) if t.is_empty()) {
+        nodes.pop();
+    }
+}
+
+/// Pushes `node`, merging adjacent text so that it is decorated as a whole.
+fn push<'a>(nodes: &mut Vec<Node<'a>>, node: Node<'a>) {
+    match (nodes.last_mut(), node) {
+        (Some(Node::Text(last)), Node::Text(t)) => last.to_mut().push_str(&t),
+        (_, node) => nodes.push(node),
+    }
+}
+
+/// How a part other than text is laid out.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Kind {
+    Inline,
+    /// Display math, which takes a paragraph of its own.
+    Display,
+    /// A hidden comment, which opens no paragraph.
+    Hidden,
+}
+
+/// Replaces the text parts with the HTML of their formatting commands, and
+/// puts the other pieces in place.
+///
+/// `pieces` are indexed the same as `parts`, and those of the text parts are
+/// ignored. Groups may span across parts, e.g. `\textbf{the set $S$}`.
+pub(super) fn render<'a>(
+    parts: &[Part<'a>],
+    pieces: Vec<Piece<'a>>,
+    job: &Job,
+    numbering: &'a Numbering,
+    bibliography: &'a Bibliography,
+    images: &'a Images,
+) -> Vec<Piece<'a>> {
+    let nodes =
+        TreeBuilder::new(tokenize(parts), job, numbering, bibliography, images).nodes(Until::Eof);
+    let mut emitter = Emitter {
+        pieces: pieces.into_iter().map(Some).collect(),
+        numbering,
+        fields: job.equation_fields,
+        kinds: parts
+            .iter()
+            .map(|p| match p.typ {
+                parser::Type::BlockMath | parser::Type::Environ => Kind::Display,
+                parser::Type::Comment => Kind::Hidden,
+                _ => Kind::Inline,
+            })
+            .collect(),
+        res: vec![],
+        styles: vec![],
+        layout: job.latex_layout,
+        in_par: !job.latex_layout,
+        skip: None,
+        pre: job.preserve_spaces && !job.latex_layout,
+        run: None,
+    };
+    emitter.emit(nodes, false);
+    emitter.end_par();
+    emitter.res
+}
+
+struct Emitter<'a> {
+    pieces: Vec<Option<Piece<'a>>>,
+    numbering: &'a Numbering,
+    /// Whether to number equations by Word fields.
+    fields: bool,
+    kinds: Vec<Kind>,
+    res: Vec<Piece<'a>>,
+    /// The tags of the styles in effect, the innermost last.
+    styles: Vec<(&'static str, &'static str)>,
+    /// Whether text is laid out in paragraphs.
+    layout: bool,
+    /// Whether a paragraph is open. Always true if `layout` is off.
+    in_par: bool,
+    /// The space above the next paragraph, in CSS.
+    skip: Option<String>,
+    /// Whether to keep the whitespace of text, in spans around each run of
+    /// text between blocks.
+    pre: bool,
+    /// The number of styles around the open span of such a run, if any.
+    run: Option<usize>,
+}
+
+impl<'a> Emitter<'a> {
+    fn emit(&mut self, nodes: Vec<Node<'a>>, italic: bool) {
+        for node in nodes {
+            match node {
+                Node::Text(t) if self.layout => {
+                    for (i, par) in split_paragraphs(&t).enumerate() {
+                        if i > 0 {
+                            self.end_par();
+                        }
+                        self.emit_text(par);
+                    }
+                }
+                Node::Text(t) => {
+                    self.begin_par();
+                    self.res.push(Piece::from_text(t));
+                }
+                Node::Piece(i) => match self.kinds[i] {
+                    Kind::Display
+                        if self
+                            .numbering
+                            .equations
+                            .contains_key(&i) =>
+                    {
+                        self.emit_numbered(i)
+                    }
+                    Kind::Display if self.layout => {
+                        self.end_par();
+                        self.begin_par();
+                        self.res.extend(self.pieces[i].take());
+                        self.end_par();
+                    }
+                    Kind::Inline => {
+                        self.begin_par();
+                        self.res.extend(self.pieces[i].take());
+                    }
+                    _ => self.res.extend(self.pieces[i].take()),
+                },
+                Node::Group(nodes) => self.emit(nodes, italic),
+                Node::Styled(style, nodes) => {
+                    let tags = style.tags(italic);
+                    self.styles.push(tags);
+                    if self.in_par {
+                        self.res
+                            .push(Piece::from_markup(tags.0));
+                    }
+                    self.emit(nodes, style.italic(italic));
+                    self.styles.pop();
+                    // a run which starts within the style
+                    if self
+                        .run
+                        .is_some_and(|depth| depth > self.styles.len())
+                    {
+                        self.end_run();
+                    }
+                    if self.in_par {
+                        self.res
+                            .push(Piece::from_markup(tags.1));
+                    }
+                }
+                Node::Par => self.end_par(),
+                Node::Break if self.in_par => self
+                    .res
+                    .push(Piece::from_markup("<br>")),
+                // there is no line to end
+                Node::Break => (),
+                Node::Skip(skip) => {
+                    self.end_par();
+                    self.skip = Some(skip);
+                }
+                // outside of lists
+                Node::Item(label) => {
+                    self.end_par();
+                    self.emit(label.unwrap_or_default(), italic);
+                }
+                Node::List(kind, items) => self.emit_list(kind, items),
+                Node::Container(open, nodes, close) => self.emit_container(open, nodes, close),
+                // outside of a `tabular`
+                Node::Tabular(_) => (),
+                Node::Field(code, result) => {
+                    self.begin_par();
+                    self.res
+                        .push(Piece::from_field(&code, result));
+                }
+                Node::Image(tag) => {
+                    self.begin_par();
+                    self.res.push(Piece::from_markup(tag));
+                }
+                Node::Block(tag, attrs, nodes) => {
+                    self.end_par();
+                    // a paragraph of its own, where the styles around do not apply
+                    let in_par = std::mem::replace(&mut self.in_par, true);
+                    let styles = std::mem::take(&mut self.styles);
+                    self.res
+                        .push(Piece::from_markup(format!("<{}{}>", tag, attrs)));
+                    self.emit(nodes, false);
+                    self.end_run();
+                    self.res
+                        .push(Piece::from_markup(format!("</{}>", tag)));
+                    self.styles = styles;
+                    self.in_par = in_par;
+                }
+            }
+        }
+    }
+
+    /// Emits text within a paragraph, where a newline is merely a space.
+    fn emit_text(&mut self, text: &str) {
+        let mut text = text;
+        if !self.in_par {
+            text = text.trim_start();
+            if text.is_empty() {
+                return;
+            }
+            self.begin_par();
+        }
+        let mut res = String::with_capacity(text.len());
+        let mut words = text.split([' ', '\t', '\r', '\n']);
+        res.extend(words.next());
+        for w in words.filter(|w| !w.is_empty()) {
+            res.push(' ');
+            res.push_str(w);
+        }
+        if text.ends_with(char::is_whitespace) && !res.ends_with(' ') {
+            res.push(' ');
+        }
+        self.res.push(Piece::from_text(res));
+    }
+
+    /// Emits an element containing paragraphs, like `<li>`.
+    fn emit_container(
+        &mut self,
+        open: Cow<'static, str>,
+        nodes: Vec<Node<'a>>,
+        close: &'static str,
+    ) {
+        self.end_par();
+        // the styles around do not apply
+        let styles = std::mem::take(&mut self.styles);
+        self.res.push(Piece::from_markup(open));
+        self.emit(nodes, false);
+        self.end_par();
+        self.res.push(Piece::from_markup(close));
+        self.styles = styles;
+    }
+
+    /// Emits a display formula with the numbers of its lines on the right.
+    fn emit_numbered(&mut self, i: usize) {
+        self.end_par();
+        self.res
+            .push(Piece::from_markup(concat!(
+                r#"<table style="width:100%;border-collapse:collapse"><tr>"#,
+                r#"<td style="width:100%;padding:0">"#,
+            )));
+        self.res.extend(self.pieces[i].take());
+        self.res.push(Piece::from_markup(
+            r#"</td><td style="padding:0;text-align:right;white-space:nowrap">"#,
+        ));
+        let numbering = self.numbering;
+        for (j, number) in numbering.equations[&i]
+            .iter()
+            .enumerate()
+        {
+            if j > 0 {
+                self.res
+                    .push(Piece::from_markup("<br>"));
+            }
+            match number {
+                Some(Number::Auto(n)) if self.fields => {
+                    // the bookmarks which `\eqref` refers to
+                    let labels = numbering.labels_of(*n);
+                    self.res.push(Piece::from_text("("));
+                    for label in &labels {
+                        let name = numbering::bookmark(label);
+                        self.res
+                            .push(Piece::from_markup(format!(r#"<a name="{}">"#, name)));
+                    }
+                    self.res
+                        .push(Piece::from_field("SEQ Equation \\* ARABIC", n.to_string()));
+                    for _ in &labels {
+                        self.res
+                            .push(Piece::from_markup("</a>"));
+                    }
+                    self.res.push(Piece::from_text(")"));
+                }
+                Some(number) => self
+                    .res
+                    .push(Piece::from_text(number.shown())),
+                None => (),
+            }
+        }
+        self.res
+            .push(Piece::from_markup("</td></tr></table>"));
+    }
+
+    fn emit_list(&mut self, kind: ListKind, items: Vec<Item<'a>>) {
+        let (open, close) = match kind {
+            ListKind::Itemize => ("<ul>", "</ul>"),
+            ListKind::Enumerate(depth) => (
+                // as the labels of nested `enumerate` in LaTeX
+                [
+                    "<ol>",
+                    r#"<ol type="a">"#,
+                    r#"<ol type="i">"#,
+                    r#"<ol type="A">"#,
+                ][depth % 4],
+                "</ol>",
+            ),
+            ListKind::Description => ("<dl>", "</dl>"),
+        };
+        let nodes = items
+            .into_iter()
+            .flat_map(|Item { label, mut body }| match (kind, label) {
+                (ListKind::Description, label) => vec![
+                    Node::Block(
+                        "dt",
+                        "",
+                        vec![Node::Styled(Style::Bold, label.unwrap_or_default())],
+                    ),
+                    Node::Container("<dd>".into(), body, "</dd>"),
+                ],
+                (_, Some(mut label)) => {
+                    // in place of the bullet or number
+                    label.push(Node::Text(" ".into()));
+                    label.append(&mut body);
+                    vec![Node::Container(
+                        r#"<li style="list-style-type:none">"#.into(),
+                        label,
+                        "</li>",
+                    )]
+                }
+                (_, None) => vec![Node::Container("<li>".into(), body, "</li>")],
+            })
+            .collect();
+        self.emit_container(open.into(), nodes, close);
+    }
+
+    fn begin_par(&mut self) {
+        self.begin_run();
+        if self.in_par {
+            return;
+        }
+        self.in_par = true;
+        self.res
+            .push(Piece::from_markup(match self.skip.take() {
+                Some(skip) => format!(r#"<p style="margin-top:{}">"#, skip).into(),
+                None => Cow::from("<p>"),
+            }));
+        for (open, _) in &self.styles {
+            self.res.push(Piece::from_markup(*open));
+        }
+    }
+
+    fn end_par(&mut self) {
+        self.end_run();
+        if !self.layout || !self.in_par {
+            return;
+        }
+        self.in_par = false;
+        if let Some(last) = self.res.last_mut() {
+            last.trim_end();
+        }
+        for (_, close) in self.styles.iter().rev() {
+            self.res
+                .push(Piece::from_markup(*close));
+        }
+        self.res
+            .push(Piece::from_markup("</p>"));
+    }
+
+    /// Opens a span of text whose whitespace is kept, if enabled.
+    fn begin_run(&mut self) {
+        if self.pre && self.run.is_none() {
+            self.run = Some(self.styles.len());
+            self.res
+                .push(Piece::from_markup(r#"<span style="white-space:pre-wrap">"#));
+        }
+    }
+
+    fn end_run(&mut self) {
+        if self.run.take().is_some() {
+            self.res
+                .push(Piece::from_markup("</span>"));
+        }
+    }
+}
+
+/// Splits `text` at blank lines.
+fn split_paragraphs(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(text);
+    std::iter::from_fn(move || {
+        let text = rest?;
+        let mut start = 0;
+        while let Some(i) = text[start..].find('\n') {
+            let line_end = start + i + 1;
+            let next = text[line_end..]
+                .find('\n')
+                .map(|j| line_end + j);
+            match next {
+                Some(j) if text[line_end..j].trim().is_empty() => {
+                    rest = Some(&text[j + 1..]);
+                    return Some(&text[..start + i]);
+                }
+                _ => start = line_end,
+            }
+        }
+        rest = None;
+        Some(text)
+    })
+}
+
+#[test]
+fn test_split_paragraphs() {
+    assert_eq!(
+        split_paragraphs("a\nb \n \n\nc\n").collect::<Vec<_>>(),
+        vec!["a\nb ", "\nc\n"]
+    );
+}
+
+/// Translates a length in TeX to CSS, if possible.
+pub(super) fn css_length(len: &str) -> Option<String> {
+    let len = len.trim();
+    let unit_start = len.find(|c: char| c.is_ascii_alphabetic())?;
+    let (value, unit) = len.split_at(unit_start);
+    let value: f32 = value.trim().parse().ok()?;
+    let unit = match unit.trim() {
+        "pt" | "bp" => "pt",
+        u @ ("cm" | "mm" | "in" | "em" | "ex" | "px") => u,
+        _ => return None,
+    };
+    Some(format!("{}{}", value, unit))
+}
+
+#[cfg(test)]
+fn render_job(source: &str, job: &Job) -> String {
+    render_bibliography(source, job, &Default::default())
+}
+
+#[cfg(test)]
+fn render_bibliography(source: &str, job: &Job, bibliography: &Bibliography) -> String {
+    let parts = parser::parse(source, &Default::default());
+    let pieces = parts
+        .iter()
+        .map(|p| match p.typ {
+            parser::Type::Text => Piece::from_text(""),
+            _ => Piece::from_math(format!("<m>{}</m>", p.as_str()).into()),
+        })
+        .collect();
+    let numbering = super::numbering::number(&parts, job.equation_offset);
+    render(
+        &parts,
+        pieces,
+        job,
+        &numbering,
+        bibliography,
+        &Default::default(),
+    )
+    .into_iter()
+    .flat_map(super::postproc::escape_html)
+    .collect()
+}
+
+#[cfg(test)]
+fn render_str(source: &str, latex_layout: bool) -> String {
+    let job = Job {
+        latex_layout,
+        ..Default::default()
+    };
+    render_job(source, &job)
+}
+
+#[test]
+fn test_render() {
+    for (source, html) in [
+        (r"a \textbf{b} c", "a <b>b</b> c"),
+        (r"\textbf {the set $S$}.", "<b>the set <m>S</m></b>."),
+        (
+            r"\emph{a \emph{b}}",
+            r#"<i>a <span style="font-style:normal">b</span></i>"#,
+        ),
+        (r"{\bf a \it b} c", "<b>a <i>b</i></b> c"),
+        (r"\textit x\underline{$y$}", "<i>x</i><u><m>y</m></u>"),
+        (r"\texttt{<a>} \foo{x}", r"<code>&lt;a&gt;</code> \foo{x}"),
+        (
+            r"a} \textsc{b",
+            r#"a} <span style="font-variant:small-caps">b</span>"#,
+        ),
+        (r"Erd\H{o}s--R\'enyi, \c c\'{\i}\ss e", "Erdős–Rényi, çíße"),
+        (
+            r"\S 2~\& \{\ldots\}, \textbf{``a''}",
+            "§2\u{a0}&amp; {…}, <b>“a”</b>",
+        ),
+        (
+            r"\textbf{see \ref} more {x} tail",
+            "<b>see ??</b> more x tail",
+        ),
+    ] {
+        assert_eq!(render_str(source, false), html);
+    }
+}
+
+#[test]
+fn test_render_preserve_spaces() {
+    let job = Job {
+        preserve_spaces: true,
+        ..Default::default()
+    };
+    let span = r#"<span style="white-space:pre-wrap">"#;
+    assert_eq!(
+        render_job("a  \\textbf{b} $x$\n c", &job),
+        format!("{}a  <b>b</b> <m>x</m>\n c</span>", span)
+    );
+    assert_eq!(
+        render_job(r"\textbf{a} b\section{S}c", &job),
+        format!(
+            "<b>{0}a</span></b>{0} b</span><h1>{0}S</span></h1>{0}c</span>",
+            span
+        )
+    );
+}
+
+#[test]
+fn test_render_layout() {
+    for (source, html) in [
+        ("a\n b\n\n  c\\par d", "<p>a b</p><p>c</p><p>d</p>"),
+        ("\\textbf{a\n\nb} c", "<p><b>a</b></p><p><b>b</b> c</p>"),
+        (
+            "a \\\\[2pt] b\\newline\n\\noindent c",
+            "<p>a <br>b<br>c</p>",
+        ),
+        (
+            "a $x$ b $$y$$ c",
+            "<p>a <m>x</m> b</p><p><m>y</m></p><p>c</p>",
+        ),
+        (
+            "\\\\a\\vspace{1cm}b\\bigskip\n\nc",
+            r#"<p>a</p><p style="margin-top:1cm">b</p><p style="margin-top:12pt">c</p>"#,
+        ),
+    ] {
+        assert_eq!(render_str(source, true), html);
+    }
+    assert_eq!(render_str("a \\\\ b\n\nc", false), "a \\\\ b\n\nc");
+}
+
+#[test]
+fn test_render_sections() {
+    let source = r"\title{On $x$}\author{A \and B\thanks{C}}\maketitle
+\section{Intro}a\subsection*[S]{Setup} \subsection{The $y$}\paragraph{P} b\section{End}";
+    let job = Job {
+        latex_layout: true,
+        number_sections: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        render_job(source, &job),
+        concat!(
+            r#"<p class="MsoTitle">On <m>x</m></p><p class="MsoSubtitle">A and B</p>"#,
+            "<h1>1 Intro</h1><p>a</p><h2>Setup</h2><h2>1.1 The <m>y</m></h2>",
+            "<h4>P</h4><p>b</p><h1>2 End</h1>",
+        )
+    );
+}
+
+#[test]
+fn test_render_lists() {
+    let source = r"\begin{itemize}
+  \item $x$ \textbf{a}
+
+  \item[--] b
+  \begin{enumerate}[label=(\alph*)]
+    \item c\begin{enumerate}\item d\end{enumerate}
+  \end{enumerate}
+\end{itemize}
+\begin{description}\item[Key $k$] value\end{description}
+\item";
+    assert_eq!(
+        render_str(source, true),
+        concat!(
+            "<ul><li><p><m>x</m> <b>a</b></p></li>",
+            r#"<li style="list-style-type:none"><p>– b</p>"#,
+            r#"<ol><li><p>c</p><ol type="a"><li><p>d</p></li></ol></li></ol></li></ul>"#,
+            "<dl><dt><b>Key <m>k</m></b></dt><dd><p>value</p></dd></dl>",
+        )
+    );
+    assert_eq!(
+        render_str(r"\begin{itemize}\item a \item b\end{itemize} c", false),
+        "<ul><li>a </li><li>b</li></ul>c"
+    );
+    // an `\end` ends its own environment only
+    let source = r"\begin{itemize}\item a \begin{quote}q\end{quote} b
+\item \begin{foo}c\end{foo}\end{itemize}";
+    assert_eq!(
+        render_str(source, true),
+        concat!(
+            "<ul><li><p>a</p><blockquote><p>q</p></blockquote><p>b</p></li>",
+            r"<li><p>\begin{foo}c\end{foo}</p></li></ul>",
+        )
+    );
+}
+
+#[test]
+fn test_render_block_environs() {
+    let source = r"\begin{center}
+  a
+\end{center}
+\begin{flushright}b\end{flushright}";
+    assert_eq!(
+        render_str(source, true),
+        concat!(
+            r#"<div style="text-align:center"><p>a</p></div>"#,
+            r#"<div style="text-align:right"><p>b</p></div>"#,
+        )
+    );
+    assert_eq!(
+        render_str(r"\begin{abstract}x\end{abstract}", false),
+        r#"<blockquote><p style="text-align:center"><b>Abstract</b></p>x</blockquote>"#
+    );
+}
+
+#[test]
+fn test_render_tabular() {
+    let source = r"\begin{table}[h]
+  \centering
+  \begin{tabular}{|l|c|}
+    \hline
+    \multicolumn{2}{|c|}{\bfseries Head} \\ \hline
+    \multirow{2}{*}{$x$} & \itshape a \& b \\
+    & c \\ \cline{2-2}
+  \end{tabular}
+  \caption{Values}
+\end{table}";
+    assert_eq!(
+        render_str(source, false),
+        [
+            r#"<table style="border-collapse:collapse"><tr>"#,
+            r#"<td colspan="2" style="padding:0 6pt;text-align:center;border-top:solid windowtext .5pt;border-left:solid windowtext .5pt;border-right:solid windowtext .5pt"><b>Head</b></td>"#,
+            "</tr><tr>",
+            r#"<td rowspan="2" style="padding:0 6pt;text-align:left;border-top:solid windowtext .5pt;border-left:solid windowtext .5pt;border-right:solid windowtext .5pt"><m>x</m></td>"#,
+            r#"<td style="padding:0 6pt;text-align:center;border-top:solid windowtext .5pt;border-right:solid windowtext .5pt"><i>a &amp; b</i></td>"#,
+            "</tr><tr>",
+            r#"<td style="padding:0 6pt;text-align:center;border-bottom:solid windowtext .5pt;border-right:solid windowtext .5pt">c</td>"#,
+            "</tr></table>",
+            r#"<p class="MsoCaption">Table 1: Values</p>"#,
+        ]
+        .concat()
+    );
+    assert_eq!(
+        render_str(r"x \begin{tabular}{r}a\\b\end{tabular} y & z", true),
+        concat!(
+            r#"<p>x</p><table style="border-collapse:collapse"><tr>"#,
+            r#"<td style="padding:0 6pt;text-align:right"><p>a</p></td></tr><tr>"#,
+            r#"<td style="padding:0 6pt;text-align:right"><p>b</p></td></tr></table>"#,
+            "<p>y &amp; z</p>",
+        )
+    );
+    // a rule before the last row is above it
+    assert_eq!(
+        render_str(
+            r"\begin{tabular}{ll}a & b\\ \hline c & d\end{tabular}",
+            false
+        ),
+        concat!(
+            r#"<table style="border-collapse:collapse"><tr>"#,
+            r#"<td style="padding:0 6pt;text-align:left">a</td>"#,
+            r#"<td style="padding:0 6pt;text-align:left">b</td></tr><tr>"#,
+            r#"<td style="padding:0 6pt;text-align:left;border-top:solid windowtext .5pt">c</td>"#,
+            r#"<td style="padding:0 6pt;text-align:left;border-top:solid windowtext .5pt">d</td>"#,
+            "</tr></table>",
+        )
+    );
+    // too many columns are cut short
+    for n in ["999999999999999", "18446744073709551615"] {
+        let source = format!(
+            r"\begin{{tabular}}{{l}}\multicolumn{{{}}}{{c}}{{x}}\end{{tabular}}",
+            n
+        );
+        assert_eq!(
+            render_str(&source, false),
+            concat!(
+                r#"<table style="border-collapse:collapse"><tr>"#,
+                r#"<td colspan="1000" style="padding:0 6pt;text-align:center">x</td>"#,
+                "</tr></table>",
+            )
+        );
+    }
+}
+
+#[test]
+fn test_render_equations() {
+    let source = r"By \eqref{e} and \ref{f},
+\begin{align}x\label{e}\\y\tag{F}\label{f}\end{align}
+and \[z\]";
+    let job = Job {
+        latex_layout: true,
+        equation_offset: 4,
+        ..Default::default()
+    };
+    assert_eq!(
+        render_job(source, &job),
+        concat!(
+            "<p>By (5) and F,</p>",
+            r#"<table style="width:100%;border-collapse:collapse"><tr><td style="width:100%;padding:0">"#,
+            r"<m>\begin{align}x\label{e}\\y\tag{F}\label{f}\end{align}</m>",
+            r#"</td><td style="padding:0;text-align:right;white-space:nowrap">(5)<br>(F)</td></tr></table>"#,
+            "<p>and</p><p><m>z</m></p>",
+        )
+    );
+}
+
+#[test]
+fn test_render_equation_fields() {
+    let job = Job {
+        equation_fields: true,
+        ..Default::default()
+    };
+    let html = render_job(
+        r"\begin{equation}x\label{e:1}\end{equation}\begin{equation}y\tag{T}\label{t}\end{equation}by \eqref{e:1}, \ref{t}",
+        &job,
+    );
+    let field = |code: &str, result: &str| {
+        format!(
+            concat!(
+                "<!--[if supportFields]><span style='mso-element:field-begin'></span> {} ",
+                "<span style='mso-element:field-separator'></span><![endif]-->{}",
+                "<!--[if supportFields]><span style='mso-element:field-end'></span><![endif]-->",
+            ),
+            code, result
+        )
+    };
+    assert!(html.contains(&format!(
+        r#">(<a name="_Ref_e_1">{}</a>)</td>"#,
+        field(r"SEQ Equation \* ARABIC", "1")
+    )));
+    assert!(html.contains(">(T)</td>"));
+    assert!(html.ends_with(&format!("by ({}), T", field(r"REF _Ref_e_1 \h", "1"))));
+}
+
+#[test]
+fn test_render_figures() {
+    let source = r"\begin{figure}[h]
+  \centering
+  \includegraphics[width=3cm]{plot.png}
+  \caption{A plot}
+\end{figure}
+\begin{table}\caption{Values}\end{table}
+\begin{figure*}\caption{Another}\end{figure*}
+\begin{figure}\begin{center}\includegraphics{p}\end{center}\caption{Plot}\end{figure}";
+    assert_eq!(
+        render_str(source, true),
+        concat!(
+            "<p>[plot.png]</p>",
+            r#"<p class="MsoCaption">Figure 1. A plot</p>"#,
+            r#"<p class="MsoCaption">Table 1: Values</p>"#,
+            r#"<p class="MsoCaption">Figure 2. Another</p>"#,
+            r#"<div style="text-align:center"><p>[p]</p></div>"#,
+            r#"<p class="MsoCaption">Figure 3. Plot</p>"#,
+        )
+    );
+}
+
+#[test]
+fn test_render_theorems() {
+    let source = r"\theoremstyle{definition}\newtheorem{axiom}[theorem]{Axiom}
+\begin{theorem}[Fermat]
+  No $n$ works.
+\end{theorem}
+\begin{axiom} A \emph{b}\end{axiom}
+\begin{lemma*}c\end{lemma*}
+\begin{proof}[Sketch] d\end{proof}";
+    assert_eq!(
+        render_str(source, true),
+        concat!(
+            r#"<p><b>Theorem 1<span style="font-weight:normal"> (Fermat)</span>.</b> "#,
+            "<i>No <m>n</m> works.</i></p>",
+            r#"<p><b>Axiom 2.</b> <span style="font-style:normal">A <i>b</i></span></p>"#,
+            "<p><b>Lemma.</b> <i>c</i></p>",
+            r#"<p><i>Sketch.</i> <span style="font-style:normal">d ∎</span></p>"#,
+        )
+    );
+    assert_eq!(
+        render_str(
+            r"a\begin{theorem}b\end{theorem}c\begin{proof}d\end{proof}",
+            false
+        ),
+        concat!(
+            "a<p><b>Theorem 1.</b> <i>b</i></p>c",
+            r#"<p><i>Proof.</i> <span style="font-style:normal">d ∎</span></p>"#,
+        )
+    );
+}
+
+#[test]
+fn test_render_citations() {
+    let bibliography = super::bibliography::parse(
+        r"@book{knuth, author = {Donald E. Knuth}, title = {The {\TeX}book},
+  publisher = {Addison-Wesley}, year = 1984}
+@article{kp, author = {Knuth, Donald and Plass, Michael}, title = {Breaking Paragraphs},
+  journal = {Software}, volume = 11, number = 11, pages = {1119--1184}, year = 1981}",
+    )
+    .unwrap();
+    let source = r"\citet{kp} and \cite[see][p.~3]{knuth,kp,x}.\bibliography{refs}";
+    let mut job = Job::default();
+    assert_eq!(
+        render_bibliography(source, &job, &bibliography),
+        concat!(
+            "Knuth and Plass [1] and [see 2, 1, ?, p.\u{a0}3].",
+            "<h1>References</h1>",
+            r#"<p style="margin-left:2em;text-indent:-2em">[1] D. Knuth and M. Plass. Breaking Paragraphs. "#,
+            "<i>Software</i>, 11(11), 1119–1184, 1981.</p>",
+            r#"<p style="margin-left:2em;text-indent:-2em">[2] D. E. Knuth. The TeXbook. Addison-Wesley, 1984.</p>"#,
+        )
+    );
+    job.citation_style = CitationStyle::AuthorYear;
+    assert_eq!(
+        render_bibliography(
+            r"\citep{kp,knuth}, \citet[ch.~1]{knuth}\nocite{*}",
+            &job,
+            &bibliography
+        ),
+        "(Knuth and Plass, 1981; Knuth, 1984), Knuth (1984, ch.\u{a0}1)"
+    );
+    let html = render_bibliography(r"\nocite{*}\printbibliography", &job, &bibliography);
+    assert!(html.contains(">D. E. Knuth (1984). The TeXbook. Addison-Wesley.</p>"));
+    assert!(html.find("D. E. Knuth") < html.find("D. Knuth and"));
+}
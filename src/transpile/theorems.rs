@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+/// A theorem-like environment, as declared by `\newtheorem`.
+#[derive(Debug, Clone)]
+pub(super) struct Theorem {
+    pub title: String,
+    /// Whether the body is italic, as in the `plain` style of amsthm, or
+    /// upright.
+    pub italic: bool,
+    /// The name of the counter, which may be shared with other environments.
+    /// Unnumbered if `None`.
+    counter: Option<String>,
+    /// The level of sectioning within which the counter is reset.
+    within: Option<usize>,
+}
+
+/// The theorem-like environments and their counters.
+#[derive(Debug)]
+pub(super) struct Theorems {
+    theorems: HashMap<String, Theorem>,
+    /// The numbers of the sections where the counters are last stepped, and
+    /// the values of the counters.
+    counters: HashMap<String, (Vec<usize>, usize)>,
+    /// Whether the body is italic under the current `\theoremstyle`.
+    italic: bool,
+}
+
+/// The environments known without `\newtheorem`, with their titles and
+/// whether they are in the `plain` style.
+const PREDEFINED: &[(&str, &str, bool)] = &[
+    ("theorem", "Theorem", true),
+    ("lemma", "Lemma", true),
+    ("proposition", "Proposition", true),
+    ("corollary", "Corollary", true),
+    ("conjecture", "Conjecture", true),
+    ("definition", "Definition", false),
+    ("example", "Example", false),
+    ("remark", "Remark", false),
+    ("note", "Note", false),
+];
+
+impl Default for Theorems {
+    fn default() -> Self {
+        let mut theorems = Theorems {
+            theorems: HashMap::new(),
+            counters: HashMap::new(),
+            italic: true,
+        };
+        for &(name, title, italic) in PREDEFINED {
+            theorems.italic = italic;
+            theorems.define(name, true, None, title, None);
+        }
+        theorems.italic = true;
+        theorems
+    }
+}
+
+impl Theorems {
+    /// Sets the style of the environments defined next, as `\theoremstyle`.
+    pub fn set_style(&mut self, style: &str) {
+        self.italic = style.trim() == "plain";
+    }
+
+    /// Defines an environment as `\newtheorem`, which shares the counter of
+    /// `shared`, or resets its counter within the sectioning unit `within`.
+    pub fn define(
+        &mut self,
+        name: &str,
+        numbered: bool,
+        shared: Option<&str>,
+        title: &str,
+        within: Option<&str>,
+    ) {
+        let counter = shared
+            .and_then(|shared| self.theorems.get(shared.trim()))
+            .map_or(Some(name.into()), |t| t.counter.clone());
+        let theorem = Theorem {
+            title: title.trim().into(),
+            italic: self.italic,
+            counter: counter.filter(|_| numbered),
+            within: within.and_then(|within| match within.trim() {
+                "section" => Some(0),
+                "subsection" => Some(1),
+                "subsubsection" => Some(2),
+                _ => None,
+            }),
+        };
+        self.theorems
+            .insert(name.into(), theorem);
+    }
+
+    /// Looks up an environment, where the starred variant of a predefined
+    /// environment is unnumbered.
+    pub fn get(&self, name: &str) -> Option<Theorem> {
+        match self.theorems.get(name) {
+            Some(theorem) => Some(theorem.clone()),
+            None => {
+                let theorem = self
+                    .theorems
+                    .get(name.strip_suffix('*')?)?;
+                Some(Theorem {
+                    counter: None,
+                    ..theorem.clone()
+                })
+            }
+        }
+    }
+
+    /// Steps the counter of `theorem`, and returns the number like `1.2`, if
+    /// it is numbered.
+    pub fn step(&mut self, theorem: &Theorem, sections: &[usize]) -> Option<String> {
+        let counter = theorem.counter.as_ref()?;
+        let prefix = match theorem.within {
+            Some(level) => sections[..=level].to_vec(),
+            None => vec![],
+        };
+        let (last, n) = self
+            .counters
+            .entry(counter.clone())
+            .or_default();
+        if *last != prefix {
+            *last = prefix;
+            *n = 0;
+        }
+        *n += 1;
+        Some(
+            last.iter()
+                .chain(Some(&*n))
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join("."),
+        )
+    }
+}
+
+#[test]
+fn test_theorems() {
+    let mut theorems = Theorems::default();
+    theorems.set_style("definition");
+    theorems.define("axiom", true, None, "Axiom", Some("section"));
+    theorems.define("claim", true, Some("lemma"), "Claim", None);
+    theorems.define("fact", false, None, "Fact", None);
+    let mut step = |name: &str, sections: &[usize]| {
+        let theorem = theorems.get(name).unwrap();
+        theorems.step(&theorem, sections)
+    };
+    assert_eq!(step("lemma", &[1]).as_deref(), Some("1"));
+    assert_eq!(step("claim", &[1]).as_deref(), Some("2"));
+    assert_eq!(step("theorem", &[1]).as_deref(), Some("1"));
+    assert_eq!(step("axiom", &[1]).as_deref(), Some("1.1"));
+    assert_eq!(step("axiom", &[1]).as_deref(), Some("1.2"));
+    assert_eq!(step("axiom", &[2]).as_deref(), Some("2.1"));
+    assert_eq!(step("lemma*", &[2]), None);
+    assert_eq!(step("fact", &[2]), None);
+    assert!(!theorems.get("axiom").unwrap().italic);
+    assert!(theorems.get("lemma").unwrap().italic);
+    assert!(theorems.get("proof").is_none());
+}
@@ -39,7 +39,7 @@ pub(super) fn run(
             let state = Rc::new(State {
                 respch,
                 reqch,
-                ploc: Default::default(),
+                ptag: Default::default(),
             });
             ctx.globals()
                 .set_func("__wait", state.clone().wait())?
@@ -62,7 +62,8 @@ pub(super) fn run(
 struct State {
     reqch: ac::Receiver<Option<comm::Request>>,
     respch: ac::Sender<comm::Response>,
-    ploc: Cell<Option<usize>>,
+    /// The (job, loc) of the request being rendered.
+    ptag: Cell<Option<(u64, usize)>>,
 }
 
 impl State {
@@ -74,7 +75,8 @@ impl State {
                 .map(|req| match req {
                     None => None,
                     Some(req) => {
-                        self.ploc.replace(Some(req.loc));
+                        self.ptag
+                            .replace(Some((req.job, req.loc)));
                         Some(req)
                     }
                 })
@@ -91,12 +93,16 @@ impl State {
                     Err(x) => panic!("{:?}", x),
                 },
             };
-            self.ploc
+            self.ptag
                 .take()
                 .ok_or("no loc")
-                .and_then(|loc| {
+                .and_then(|(job, loc)| {
                     self.respch
-                        .send_blocking(comm::Response { loc, omml: res })
+                        .send_blocking(comm::Response {
+                            job,
+                            loc,
+                            omml: res,
+                        })
                         .map_err(|_| "send channel closed")
                 })
                 .throw(ctx)
@@ -7,9 +7,9 @@ use std::{
 use super::app_delegate::*;
 use super::app_layout;
 use super::model::Model;
-use crate::transpile;
 pub(super) use app_ui::AppUi;
 use nwg::{CheckBoxState, NativeUi};
+use textocx::transpile;
 
 #[derive(Debug)]
 pub(super) struct State {
@@ -17,6 +17,7 @@ pub(super) struct State {
     pub(super) model: Model,
     prev_queue_at: time::Instant,
     omml: Option<String>,
+    rendered_msg: String,
 }
 
 #[derive(nwd::NwgUi, Default)]
@@ -33,6 +34,18 @@ pub struct App {
     #[nwg_events(OnButtonClick: [App::try_queue_job(SELF)])]
     pub(super) preserve_spaces_check_box: nwg::CheckBox,
 
+    #[nwg_control(parent: window, focus: true, text: "LaTeX Layout")]
+    #[nwg_events(OnButtonClick: [App::try_queue_job(SELF)])]
+    pub(super) latex_layout_check_box: nwg::CheckBox,
+
+    #[nwg_control(parent: window, focus: true, text: "Equation Fields")]
+    #[nwg_events(OnButtonClick: [App::try_queue_job(SELF)])]
+    pub(super) equation_fields_check_box: nwg::CheckBox,
+
+    #[nwg_control(parent: window, focus: true, text: "Tolerate Errors", check_state: CheckBoxState::Checked)]
+    #[nwg_events(OnButtonClick: [App::try_queue_job(SELF)])]
+    pub(super) tolerant_check_box: nwg::CheckBox,
+
     #[nwg_control(parent: window, focus: true, text: "Auto Copy", check_state: CheckBoxState::Checked)]
     pub(super) auto_copy_check_box: nwg::CheckBox,
 
@@ -82,7 +95,11 @@ impl App {
                 .preserve_spaces_check_box
                 .check_state()
                 == CheckBoxState::Checked,
+            latex_layout: is_checked(&self.latex_layout_check_box),
+            equation_fields: is_checked(&self.equation_fields_check_box),
+            tolerant: is_checked(&self.tolerant_check_box),
             tex_code: self.tex_edit.text(),
+            ..Default::default()
         });
         comm.prev_queue_at = time::Instant::now();
     }
@@ -94,10 +111,10 @@ impl App {
             Some(msg) => msg,
             _ => return,
         };
-        self.paste(msg.as_bytes());
+        self.paste(msg.as_bytes(), &comm.rendered_msg);
     }
 
-    fn paste(&self, msg: &[u8]) {
+    fn paste(&self, msg: &[u8], rendered_msg: &str) {
         let res = (move || {
             let _cb = clipboard_win::Clipboard::new_attempts(10)?;
             let fmt = clipboard_win::raw::register_format("HTML Format").unwrap();
@@ -105,10 +122,13 @@ impl App {
         })();
         self.status_bar.set_text(
             0,
-            if res.is_ok() {
-                "Rendered. Copied to clipboard."
+            &if res.is_ok() {
+                format!("{} Copied to clipboard.", rendered_msg)
             } else {
-                "Rendered. Fail to write clipboard, maybe try again later."
+                format!(
+                    "{} Fail to write clipboard, maybe try again later.",
+                    rendered_msg
+                )
             },
         );
         self.toggle_copy_button(res.is_err())
@@ -133,12 +153,21 @@ impl App {
                     .status_bar
                     .set_text(0, "Rendering..."),
                 Msg::UpdateOmml(res) => match res {
-                    Ok(omml) => {
+                    Ok(output) => {
+                        state.rendered_msg = match output.errors.first() {
+                            None => "Rendered.".into(),
+                            Some(e) => format!(
+                                "Rendered with {} error(s), the first is {}.",
+                                output.errors.len(),
+                                e
+                            ),
+                        };
                         if is_checked(&self.auto_copy_check_box) {
-                            to_paste.replace(omml);
+                            to_paste.replace(output.content);
                         } else {
-                            state.omml.replace(omml);
-                            self.status_bar.set_text(0, "Rendered.");
+                            state.omml.replace(output.content);
+                            self.status_bar
+                                .set_text(0, &state.rendered_msg);
                             self.toggle_copy_button(true)
                         }
                     }
@@ -148,9 +177,10 @@ impl App {
                 },
             }
         }
+        let rendered_msg = state.rendered_msg.clone();
         drop(bor);
         if let Some(msg) = to_paste {
-            self.paste(msg.as_bytes())
+            self.paste(msg.as_bytes(), &rendered_msg)
         }
     }
 }
@@ -169,6 +199,7 @@ impl App {
             model,
             prev_queue_at: time::Instant::now(),
             omml: None,
+            rendered_msg: String::new(),
         }));
         Self::layout_self(&app)?;
         Ok(app)
@@ -2,7 +2,7 @@ use std::sync::mpsc;
 
 use nwg::NoticeSender;
 
-use crate::transpile;
+use textocx::transpile;
 
 pub(super) trait Delegate: Send + 'static {
     fn set_solving_status(&self);
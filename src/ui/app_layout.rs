@@ -30,6 +30,12 @@ impl App {
             .child_size(TOOLBOX_SIZE)
             .child(&app.preserve_spaces_check_box)
             .child_size(TOOLBOX_SIZE)
+            .child(&app.latex_layout_check_box)
+            .child_size(TOOLBOX_SIZE)
+            .child(&app.equation_fields_check_box)
+            .child_size(TOOLBOX_SIZE)
+            .child(&app.tolerant_check_box)
+            .child_size(TOOLBOX_SIZE)
             .child(&app.auto_copy_check_box)
             .child_size(TOOLBOX_SIZE)
             .child(&app.copy_button)
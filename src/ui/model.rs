@@ -1,16 +1,20 @@
 use std::{sync::mpsc, thread::JoinHandle};
 
 use super::app_delegate::*;
-use crate::transpile;
+use textocx::transpile;
 
 #[derive(Debug)]
 pub(super) struct Model {
     jh: Option<JoinHandle<()>>,
     job_sender: mpsc::Sender<Option<transpile::Job>>,
+    cancel: Option<transpile::CancelHandle>,
 }
 
 impl Drop for Model {
     fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel.cancel()
+        }
         self.job_sender
             .send(None)
             .expect("fail to stop model thread");
@@ -22,21 +26,46 @@ impl Drop for Model {
 
 impl Model {
     pub fn new<D: Delegate>(delegate: D) -> Self {
-        let solver = transpile::Solver::new(2);
+        let mut solver = transpile::Solver::new(2);
+        let beside_exe = |name: &str| {
+            std::env::current_exe()
+                .map(|p| p.with_file_name(name))
+                .ok()
+                .filter(|p| p.exists())
+        };
+        // shared macros are kept in a `macros.tex` beside the executable, and
+        // the references in a `references.bib` or `references.json`
+        let mut errors = vec![];
+        errors.extend(beside_exe("macros.tex").and_then(|p| solver.load_preamble(p).err()));
+        errors.extend(
+            beside_exe("references.bib")
+                .or_else(|| beside_exe("references.json"))
+                .and_then(|p| solver.load_bibliography(p).err()),
+        );
         let (jobs, jobr) = mpsc::channel::<Option<transpile::Job>>();
         let jh = std::thread::spawn(move || {
+            for e in errors {
+                delegate.update_omml(Err(e));
+            }
             while let Some(job) = jobr.recv().unwrap() {
                 delegate.set_solving_status();
-                let result = solver.solve(job);
-                delegate.update_omml(result);
+                match solver.solve(job) {
+                    // superseded by a newer job
+                    Err(transpile::Error::Cancelled) => (),
+                    result => delegate.update_omml(result),
+                }
             }
         });
         Self {
             jh: Some(jh),
             job_sender: jobs,
+            cancel: None,
         }
     }
-    pub fn queue_job(&self, job: transpile::Job) {
+    pub fn queue_job(&mut self, job: transpile::Job) {
+        if let Some(prev) = self.cancel.replace(job.cancel.clone()) {
+            prev.cancel()
+        }
         self.job_sender.send(Some(job)).unwrap();
     }
 }